@@ -1,21 +1,38 @@
-use candid::candid_method;
+use candid::{candid_method, CandidType};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
 
 use ego_macros::{inject_app_info, inject_ego_api, inject_ego_data};
 use ic_cdk_macros::*;
 use std::cell::RefCell;
 
 use wallet_canister_mod::types::{
-    CallCanisterArgs, CallResult, ExpiryUser, MethodType, MethodValidationType, OwnerReply,
-    ProxyActorTargets, QueueHash,
+    Account, Approval, ApprovalRevocation, AuditFilter, AuditPage, BalanceResult, BatchCall,
+    BatchMode, BatchResult, CallCanisterArgs, CallResult, CertifiedData, CreateCanisterArgs,
+    CreateResult, Decision, Denial, ExpiryUser, MethodType, MethodValidationType, OwnerReply,
+    ProxyActorTargets, ProxyCallResult, QueueFilter, QueueHash, QueuePage, QueueStatus,
+    RateCounter, RateLimit, ReceiveOptions, ScheduledCall, SendCyclesArgs, StableWalletStore,
+    TokenAllowance,
 };
 
-use ic_cdk::trap;
+use wallet_canister_mod::auth;
+use wallet_canister_mod::certified;
+use wallet_canister_mod::cycles_wallet;
+use wallet_canister_mod::memory;
+use wallet_canister_mod::nft;
 use wallet_canister_mod::service::WalletService;
+use wallet_canister_mod::token;
 
 inject_ego_api!();
 inject_ego_data!();
 inject_app_info!();
 
+const MAX_QUEUE_PAGE: usize = 100;
+const MAX_AUDIT_PAGE: usize = 100;
+const MIN_SCHEDULE_INTERVAL: u64 = 60 * 1000 * 1000 * 1000;
+const MAX_BATCH_CALLS: usize = 16;
+const MAX_SCHEDULED_CALLS: usize = 16;
+
 /********************  methods for canister_registry_macro   ********************/
 fn on_canister_added(name: &str, canister_id: Principal) {
     ic_cdk::println!(
@@ -27,88 +44,22 @@ fn on_canister_added(name: &str, canister_id: Principal) {
 }
 
 #[inline(always)]
-pub fn owner_or_valid_user_guard() -> Result<(), String> {
+pub fn owner_or_approver_guard() -> Result<(), String> {
     let caller = ic_cdk::api::caller();
-    if is_owner(caller.clone()) || (WalletService::is_valid_user(&caller.clone())) {
+    if is_owner(caller) || WalletService::is_approver(&caller) {
         Ok(())
     } else {
-        trap(&format!("{} unauthorized", caller));
+        Err(format!("{} unauthorized", caller))
     }
 }
 
-pub fn targets_guard(args: CallCanisterArgs<u128>) -> Result<Option<String>, String> {
-    let caller = caller();
-
-    if !is_owner(caller) {
-        match WalletService::is_proxy_black_list(&args.canister) {
-            true => trap(&format!(
-                "Canister {} is in proxy black list",
-                args.canister.clone()
-            )),
-            false => match WalletService::is_valid_canister(&caller.clone(), &args.canister) {
-                true => {
-                    match WalletService::is_valid_canister_method(
-                        &caller.clone(),
-                        &args.canister,
-                        &args.method_name,
-                    ) {
-                        true => match WalletService::get_method_validate_type() {
-                            MethodValidationType::ALL => {
-                                let obj = WalletService::hash_method(&caller.clone(), args);
-                                let hash = WalletService::add_method_queue(obj);
-                                Ok(Some(hash))
-                            }
-                            MethodValidationType::UPDATE => {
-                                match WalletService::get_method_type(
-                                    &caller.clone(),
-                                    &args.canister,
-                                    &args.method_name,
-                                ) {
-                                    None => trap(&format!(
-                                        "Method {} is not in authorized targets",
-                                        args.method_name.clone()
-                                    )),
-                                    Some(r) => {
-                                        if r == MethodType::CALL {
-                                            let obj =
-                                                WalletService::hash_method(&caller.clone(), args);
-                                            let hash = WalletService::add_method_queue(obj);
-                                            Ok(Some(hash))
-                                        } else {
-                                            Ok(None)
-                                        }
-                                    }
-                                }
-                            }
-                            MethodValidationType::KEY => {
-                                match WalletService::is_method_key_oper(
-                                    &caller.clone(),
-                                    &args.canister,
-                                    &args.method_name,
-                                ) {
-                                    true => {
-                                        let obj = WalletService::hash_method(&caller.clone(), args);
-                                        let hash = WalletService::add_method_queue(obj);
-                                        Ok(Some(hash))
-                                    }
-                                    false => Ok(None),
-                                }
-                            }
-                        },
-                        false => trap(&format!(
-                            "Method {} is not in authorized targets",
-                            args.method_name.clone()
-                        )),
-                    }
-                }
-                false => trap(&format!(
-                    "Canister {} is not in authorized targets",
-                    args.canister.clone()
-                )),
-            },
-        }
+#[inline(always)]
+pub fn owner_or_valid_user_guard() -> Result<(), String> {
+    let caller = ic_cdk::api::caller();
+    if is_owner(caller) || (WalletService::is_valid_user(&caller.clone())) {
+        Ok(())
     } else {
-        Ok(None)
+        Err(format!("{} unauthorized", caller))
     }
 }
 
@@ -118,39 +69,461 @@ pub fn init() {
     let caller = ic_cdk::api::caller();
     ic_cdk::println!("wallet canister: init, caller is {}", caller.clone());
     ic_cdk::println!("==> add caller as the owner");
-    owner_add(caller.clone());
+    owner_add(caller);
+    wallet_canister_mod::start_queue_sweep();
+    certified::certify_root();
+}
+
+fn arm_scheduler() {
+    wallet_canister_mod::arm_scheduler(|| ic_cdk::spawn(run_scheduled_calls()));
+}
+
+async fn run_scheduled_calls() {
+    let due = WalletService::take_due_scheduled_calls(ic_cdk::api::time());
+    arm_scheduler();
+    for call in due {
+        let result = proxy(call.user, call.payload).await;
+        WalletService::record_scheduled_result(call.id, result);
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct StableState {
+    users: User,
+    registry: Registry,
+    app_info: AppInfo,
+    wallet_store: StableWalletStore,
 }
 
-#[update(name = "proxy_call", guard = "owner_or_valid_user_guard")]
+#[pre_upgrade]
+fn pre_upgrade() {
+    ic_cdk::println!("wallet canister: pre_upgrade");
+    let stable_state = StableState {
+        users: users_pre_upgrade(),
+        registry: registry_pre_upgrade(),
+        app_info: app_info_pre_upgrade(),
+        wallet_store: wallet_canister_mod::service::pre_upgrade(),
+    };
+    let bytes = candid::encode_one(stable_state).expect("failed to encode stable state");
+    memory::save_upgrade_bytes(&bytes);
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    ic_cdk::println!("wallet canister: post_upgrade");
+    let stable_state: Option<StableState> = if memory::has_raw_candid_state() {
+        // schema version 1 was saved with `stable_save` over the whole stable memory,
+        // read it before the memory manager claims the space
+        let (stable_state,) =
+            ic_cdk::storage::stable_restore().expect("failed to restore stable state");
+        Some(stable_state)
+    } else {
+        memory::load_upgrade_bytes()
+            .map(|bytes| candid::decode_one(&bytes).expect("failed to decode stable state"))
+    };
+    match stable_state {
+        None => {
+            // releases without upgrade hooks left nothing in stable memory
+            ic_cdk::println!("==> no stable state found, add caller as the owner");
+            owner_add(ic_cdk::api::caller());
+        }
+        Some(stable_state) => {
+            users_post_upgrade(stable_state.users);
+            registry_post_upgrade(stable_state.registry);
+            app_info_post_upgrade(stable_state.app_info);
+            wallet_canister_mod::service::post_upgrade(stable_state.wallet_store);
+        }
+    }
+    wallet_canister_mod::start_queue_sweep();
+    wallet_canister_mod::start_certification_backfill();
+    certified::certify_root();
+    arm_scheduler();
+}
+
+#[update(name = "proxy_call")]
 #[candid_method(update, rename = "proxy_call")]
-async fn proxy_call(args: CallCanisterArgs<u128>) -> Result<CallResult, String> {
-    match targets_guard(args.clone()) {
-        Ok(hash) => {
-            if hash.is_none() {
-                wallet_canister_mod::wallet_call(args.clone()).await
+async fn proxy_call(args: CallCanisterArgs<u128>) -> ProxyCallResult {
+    proxy(caller(), args).await
+}
+
+/// Reads through the wallet without consensus: forwards `args` as a query when
+/// `auth::check_query` allows it.
+#[query(name = "proxy_query", composite = true)]
+#[candid_method(query, rename = "proxy_query")]
+async fn proxy_query(args: CallCanisterArgs<u128>) -> ProxyCallResult {
+    let caller = caller();
+    if let Err(reason) = auth::check_query(&caller, is_owner(caller), &args, ic_cdk::api::time()) {
+        return ProxyCallResult::Unauthorized { reason };
+    }
+    match wallet_canister_mod::query_call(args).await {
+        Ok(r) => ProxyCallResult::Executed(r),
+        Err((code, message)) => ProxyCallResult::Rejected {
+            reject_code: code as u8,
+            message,
+        },
+    }
+}
+
+/// Authorizes and forwards a call made by `caller`, directly or through a schedule.
+async fn proxy(caller: Principal, args: CallCanisterArgs<u128>) -> ProxyCallResult {
+    match auth::authorize(&caller, is_owner(caller), args.clone()) {
+        Ok(None) => forward_authorized(caller, args).await,
+        Ok(Some((hash, expires_at))) => ProxyCallResult::Queued {
+            id: hash,
+            expires_at,
+        },
+        Err(reason) => ProxyCallResult::Unauthorized { reason },
+    }
+}
+
+/// Forwards a call `auth` let through and charged, and gives back what the
+/// call didn't use.
+async fn forward_authorized(caller: Principal, args: CallCanisterArgs<u128>) -> ProxyCallResult {
+    let owner = is_owner(caller);
+    if !owner
+        && WalletService::get_method_type(&caller, &args.canister, &args.method_name)
+            == Some(MethodType::OneWay)
+    {
+        return match wallet_canister_mod::notify_as(caller, args.clone(), None) {
+            Ok(id) => ProxyCallResult::Submitted { id },
+            Err((code, message)) => {
+                let cycles = args.cycles;
+                WalletService::refund_cycles(&caller, &args.canister, &args.method_name, cycles);
+                token::release_transfer(&caller, &args);
+                nft::release_transfers(&caller, &args, None);
+                ProxyCallResult::Rejected {
+                    reject_code: code as u8,
+                    message,
+                }
+            }
+        };
+    }
+    let (result, refunded) =
+        wallet_canister_mod::wallet_call_with_refund(caller, args.clone()).await;
+    if !owner {
+        WalletService::refund_cycles(&caller, &args.canister, &args.method_name, refunded);
+        let failed = match &result {
+            Ok(reply) => token::transfer_failed(&reply.r#return),
+            Err(_) => true,
+        };
+        if failed {
+            token::release_transfer(&caller, &args);
+        }
+        let reply = result.as_ref().ok().map(|r| r.r#return.as_slice());
+        nft::release_transfers(&caller, &args, reply);
+    }
+    match result {
+        Ok(r) => ProxyCallResult::Executed(r),
+        Err((code, message)) => ProxyCallResult::Rejected {
+            reject_code: code as u8,
+            message,
+        },
+    }
+}
+
+/********************  dfx cycles wallet interface   ********************/
+
+/// `proxy_call` in the shape of the dfx cycles wallet, so `dfx --wallet` can
+/// use it. Calls that are queued or sent one-way come back as errors.
+#[update(name = "wallet_call")]
+#[candid_method(update, rename = "wallet_call")]
+async fn wallet_call(args: CallCanisterArgs<u64>) -> Result<CallResult, String> {
+    cycles_wallet::call_result(proxy(caller(), args.into()).await)
+}
+
+#[update(name = "wallet_call128")]
+#[candid_method(update, rename = "wallet_call128")]
+async fn wallet_call128(args: CallCanisterArgs<u128>) -> Result<CallResult, String> {
+    cycles_wallet::call_result(proxy(caller(), args).await)
+}
+
+#[query(name = "wallet_balance", guard = "owner_or_valid_user_guard")]
+#[candid_method(query, rename = "wallet_balance")]
+fn wallet_balance() -> BalanceResult<u64> {
+    BalanceResult {
+        amount: ic_cdk::api::canister_balance(),
+    }
+}
+
+#[query(name = "wallet_balance128", guard = "owner_or_valid_user_guard")]
+#[candid_method(query, rename = "wallet_balance128")]
+fn wallet_balance128() -> BalanceResult<u128> {
+    BalanceResult {
+        amount: ic_cdk::api::canister_balance128(),
+    }
+}
+
+/// Deposits cycles into a canister through the management canister's
+/// `deposit_cycles`, which delegates must be allowed to call.
+#[update(name = "wallet_send")]
+#[candid_method(update, rename = "wallet_send")]
+async fn wallet_send(args: SendCyclesArgs<u64>) -> Result<(), String> {
+    let call = cycles_wallet::deposit_cycles_call(args.canister, args.amount as u128);
+    cycles_wallet::call_result(proxy(caller(), call).await).map(|_| ())
+}
+
+/// Accepts all cycles sent with the call, from anyone.
+#[update(name = "wallet_receive")]
+#[candid_method(update, rename = "wallet_receive")]
+fn wallet_receive(_options: Option<ReceiveOptions>) {
+    let available = ic_cdk::api::call::msg_cycles_available128();
+    ic_cdk::api::call::msg_cycles_accept128(available);
+}
+
+/// Creates a canister through the management canister's `create_canister`,
+/// which delegates must be allowed to call.
+#[update(name = "wallet_create_canister")]
+#[candid_method(update, rename = "wallet_create_canister")]
+async fn wallet_create_canister(args: CreateCanisterArgs<u64>) -> Result<CreateResult, String> {
+    let call = cycles_wallet::create_canister_call(CreateCanisterArgs {
+        cycles: args.cycles as u128,
+        settings: args.settings,
+    });
+    cycles_wallet::create_result(proxy(caller(), call).await)
+}
+
+/// Proxies `calls` in order for the caller and stops at the first call that
+/// doesn't run. Every call is checked and charged first, see
+/// `auth::authorize_batch`: when one is denied nothing runs, and when one needs
+/// approval the whole batch is queued as one unit. Calls that don't run get
+/// their charge back. A call taking its arguments from an earlier reply is
+/// checked again with those arguments right before it runs, see `BatchMode`
+/// for when it needs approval then.
+#[update(name = "proxy_call_batch")]
+#[candid_method(update, rename = "proxy_call_batch")]
+async fn proxy_call_batch(calls: Vec<BatchCall>, mode: BatchMode) -> Result<BatchResult, String> {
+    if calls.is_empty() || calls.len() > MAX_BATCH_CALLS {
+        return Err(format!("A batch holds 1 to {} calls", MAX_BATCH_CALLS));
+    }
+    if let Some(index) =
+        (0..calls.len()).find(|&i| calls[i].args_from.is_some_and(|f| f as usize >= i))
+    {
+        return Err(format!(
+            "Call {} can only take its arguments from an earlier call",
+            index
+        ));
+    }
+    let caller = caller();
+    let owner = is_owner(caller);
+    let payloads = calls.iter().map(|c| c.call.clone()).collect::<Vec<_>>();
+    match auth::authorize_batch(&caller, owner, &payloads) {
+        Ok(Decision::Execute) => {}
+        Ok(_) => {
+            return Ok(BatchResult {
+                results: vec![queue_batch(caller, &calls, 0, &[])],
+                stopped_at: Some(0),
+            })
+        }
+        Err((index, reason)) => {
+            return Ok(BatchResult {
+                results: vec![ProxyCallResult::Unauthorized { reason }],
+                stopped_at: Some(index as u32),
+            })
+        }
+    }
+
+    // every call is charged, so one that doesn't run has its charge given back
+    let release_from = |from: usize, queued: bool| {
+        for args in payloads[from..].iter() {
+            auth::release(&caller, owner, args, queued);
+        }
+    };
+    let mut replies = vec![];
+    let mut results = vec![];
+    for index in 0..calls.len() {
+        let args = calls[index]
+            .resolve(&replies)
+            .expect("every earlier call has replied");
+        let decision = match calls[index].args_from {
+            None => Decision::Execute,
+            Some(_) => auth::reauthorize(&caller, owner, &payloads[index], &args),
+        };
+        let result = match (decision, mode) {
+            (Decision::Execute, _) => forward_authorized(caller, args).await,
+            (Decision::Queue, BatchMode::Sequential) => {
+                auth::record_call(&caller, owner, &args);
+                release_from(index + 1, true);
+                queue_batch(caller, &calls, index, &replies)
+            }
+            (Decision::Queue, BatchMode::AllOrNothing) => {
+                release_from(index + 1, false);
+                ProxyCallResult::Unauthorized {
+                    reason: Denial::ApprovalRequired,
+                }
+            }
+            (Decision::Deny(reason), _) => {
+                release_from(index + 1, false);
+                ProxyCallResult::Unauthorized { reason }
+            }
+        };
+        match &result {
+            ProxyCallResult::Executed(reply) => replies.push(reply.clone()),
+            ProxyCallResult::Submitted { .. } => replies.push(CallResult { r#return: vec![] }),
+            _ => {
+                if let ProxyCallResult::Rejected { .. } = result {
+                    release_from(index + 1, false);
+                }
+                results.push(result);
+                return Ok(BatchResult {
+                    results,
+                    stopped_at: Some(index as u32),
+                });
+            }
+        }
+        results.push(result);
+    }
+    Ok(BatchResult {
+        results,
+        stopped_at: None,
+    })
+}
+
+/// Queues `calls[from..]` as one unit. References to calls that already ran
+/// are replaced by their replies.
+fn queue_batch(
+    caller: Principal,
+    calls: &[BatchCall],
+    from: usize,
+    replies: &[CallResult],
+) -> ProxyCallResult {
+    let rest = calls[from..]
+        .iter()
+        .map(|c| match c.args_from {
+            Some(index) if (index as usize) < from => BatchCall {
+                call: c.resolve(replies).expect("every earlier call has replied"),
+                args_from: None,
+            },
+            Some(index) => BatchCall {
+                call: c.call.clone(),
+                args_from: Some(index - from as u32),
+            },
+            None => c.clone(),
+        })
+        .collect();
+    let item = WalletService::hash_batch(&caller, rest);
+    let expires_at = item.expires_at;
+    ProxyCallResult::Queued {
+        id: WalletService::add_method_queue(item),
+        expires_at,
+    }
+}
+
+/// Dry run of `proxy_call`: what the wallet would do with `args` sent by the caller.
+#[query(name = "check_call")]
+#[candid_method(query, rename = "check_call")]
+fn check_call(args: CallCanisterArgs<u128>) -> Decision {
+    let caller = caller();
+    auth::check_call(&caller, is_owner(caller), &args, ic_cdk::api::time())
+}
+
+/// Schedules `args` to be proxied for the caller at `at`, and every `interval`
+/// after that if set. The call must pass `check_call` now and again on every run.
+/// A call is dropped once it won't run again, and with the session of its delegate.
+#[update(name = "schedule_call", guard = "owner_or_valid_user_guard")]
+#[candid_method(update, rename = "schedule_call")]
+fn schedule_call(
+    args: CallCanisterArgs<u128>,
+    at: u64,
+    interval: Option<u64>,
+) -> Result<ScheduledCall, String> {
+    let caller = caller();
+    if interval.is_some_and(|i| i < MIN_SCHEDULE_INTERVAL) {
+        return Err(format!(
+            "Interval must be at least {} nanoseconds",
+            MIN_SCHEDULE_INTERVAL
+        ));
+    }
+    if !is_owner(caller) && WalletService::count_scheduled_calls(&caller) >= MAX_SCHEDULED_CALLS {
+        return Err(format!(
+            "A delegate can have at most {} scheduled calls",
+            MAX_SCHEDULED_CALLS
+        ));
+    }
+    if let Decision::Deny(denial) =
+        auth::check_call(&caller, is_owner(caller), &args, ic_cdk::api::time())
+    {
+        return Err(denial.to_string());
+    }
+    let call = WalletService::add_scheduled_call(caller, args, at, interval);
+    arm_scheduler();
+    Ok(call)
+}
+
+#[update(name = "cancel_scheduled_call", guard = "owner_or_valid_user_guard")]
+#[candid_method(update, rename = "cancel_scheduled_call")]
+fn cancel_scheduled_call(id: u64) -> Result<Option<ScheduledCall>, String> {
+    let caller = caller();
+    match WalletService::get_scheduled_call(id) {
+        None => Ok(None),
+        Some(r) => {
+            if is_owner(caller) || r.user.eq(&caller) {
+                let removed = WalletService::remove_scheduled_call(id);
+                arm_scheduler();
+                Ok(removed)
             } else {
-                Err(hash.unwrap())
+                Err("Not Authorized Execution".to_string())
             }
         }
-        Err(r) => trap(&r),
     }
 }
 
-#[update(name = "owner_confirm", guard = "owner_guard")]
+/// Every scheduled call for the owner, the caller's own for a delegate.
+#[query(name = "list_scheduled_calls", guard = "owner_or_valid_user_guard")]
+#[candid_method(query, rename = "list_scheduled_calls")]
+fn list_scheduled_calls() -> Vec<ScheduledCall> {
+    let caller = caller();
+    if is_owner(caller) {
+        WalletService::list_scheduled_calls(None)
+    } else {
+        WalletService::list_scheduled_calls(Some(&caller))
+    }
+}
+
+#[update(name = "owner_confirm", guard = "owner_or_approver_guard")]
 #[candid_method(update, rename = "owner_confirm")]
-async fn owner_confirm(hash: String, approve: bool) -> OwnerReply {
-    match WalletService::get_queue_method(hash.clone()) {
-        None => OwnerReply::NotFound,
-        Some(r) => {
-            if approve {
-                let call_result = wallet_canister_mod::wallet_call(r.payload.clone()).await;
-                let reply = OwnerReply::Approved(call_result);
-                WalletService::update_queue_reply(hash.clone(), reply.clone());
-                reply.clone()
+async fn owner_confirm(hash: String, approve: bool) -> Result<OwnerReply, String> {
+    let caller = caller();
+    if WalletService::has_approvers() && !WalletService::is_approver(&caller) {
+        return Err(format!("{} is not an approver", caller));
+    }
+    // the deciding vote leaves `Pending` before awaiting, so the call can't run twice
+    match WalletService::vote_queue_method(&hash, caller, approve) {
+        Err(None) => Ok(OwnerReply::NotFound),
+        Err(Some(status)) => Err(format!("Queued call {} is {:?}", hash, status)),
+        Ok(r) => {
+            if r.status() == QueueStatus::Executing {
+                let method_type = r.method_type(0);
+                let (call_result, batch_results) = match r.batch {
+                    None => (
+                        wallet_canister_mod::wallet_call_approved(
+                            r.user,
+                            r.payload,
+                            method_type,
+                            &hash,
+                        )
+                        .await,
+                        None,
+                    ),
+                    Some(calls) => {
+                        let (replies, result) = wallet_canister_mod::wallet_call_batch(
+                            r.user,
+                            calls,
+                            r.method_types,
+                            &hash,
+                        )
+                        .await;
+                        (result, Some(replies))
+                    }
+                };
+                Ok(WalletService::finish_queue_method(
+                    &hash,
+                    call_result,
+                    batch_results,
+                ))
+            } else if r.status() == QueueStatus::Pending {
+                Ok(WalletService::pending_reply(&r))
             } else {
-                let reply = OwnerReply::Rejected(hash.clone());
-                WalletService::update_queue_reply(hash.clone(), reply.clone());
-                reply.clone()
+                Ok(r.owner_reply)
             }
         }
     }
@@ -168,12 +541,50 @@ fn get_queue_reply(hash: String) -> Option<OwnerReply> {
     WalletService::get_queue_reply(hash)
 }
 
-#[query(name = "get_queue_unconfirmed", guard = "owner_guard")]
+#[query(name = "get_queue_status", guard = "owner_or_valid_user_guard")]
+#[candid_method(query, rename = "get_queue_status")]
+fn get_queue_status(hash: String) -> Option<QueueStatus> {
+    WalletService::get_queue_method(hash).map(|r| r.status())
+}
+
+#[query(name = "get_queue_unconfirmed", guard = "owner_or_approver_guard")]
 #[candid_method(query, rename = "get_queue_unconfirmed")]
 fn get_queue_unconfirmed(user: Principal) -> Vec<QueueHash> {
     WalletService::get_queue_unconfirmed(&user)
 }
 
+#[query(name = "list_queue", guard = "owner_or_approver_guard")]
+#[candid_method(query, rename = "list_queue")]
+fn list_queue(filter: QueueFilter, cursor: Option<String>, limit: u32) -> QueuePage {
+    let limit = (limit as usize).clamp(1, MAX_QUEUE_PAGE);
+    WalletService::list_queue(&filter, cursor, limit)
+}
+
+#[query(name = "list_audit_log", guard = "owner_guard")]
+#[candid_method(query, rename = "list_audit_log")]
+fn list_audit_log(filter: AuditFilter, cursor: Option<u64>, limit: u32) -> AuditPage {
+    let limit = (limit as usize).clamp(1, MAX_AUDIT_PAGE);
+    WalletService::list_audit_log(&filter, cursor, limit)
+}
+
+/// `list_audit_log` without filters, certified; see `CertifiedData`.
+#[query(name = "get_certified_audit_log", guard = "owner_guard")]
+#[candid_method(query, rename = "get_certified_audit_log")]
+fn get_certified_audit_log(from: u64, limit: u32) -> CertifiedData {
+    let limit = (limit as usize).clamp(1, MAX_AUDIT_PAGE);
+    certified::audit_entries(from, limit as u64)
+}
+
+/// The queued call `hash`, certified; see `CertifiedData`.
+#[query(
+    name = "get_certified_queue_method",
+    guard = "owner_or_valid_user_guard"
+)]
+#[candid_method(query, rename = "get_certified_queue_method")]
+fn get_certified_queue_method(hash: String) -> CertifiedData {
+    certified::queue_item(&hash)
+}
+
 #[update(name = "remove_queue_method", guard = "owner_or_valid_user_guard")]
 #[candid_method(update, rename = "remove_queue_method")]
 fn remove_queue_method(hash: String) -> Result<bool, String> {
@@ -181,7 +592,9 @@ fn remove_queue_method(hash: String) -> Result<bool, String> {
     match WalletService::get_queue_method(hash.clone()) {
         None => Ok(false),
         Some(r) => {
-            if is_owner(caller) || r.user.eq(&caller) {
+            if r.status() == QueueStatus::Executing {
+                Err("Queued call is executing".to_string())
+            } else if is_owner(caller) || r.user.eq(&caller) {
                 Ok(WalletService::remove_queue_method(hash.clone()).is_some())
             } else {
                 Err("Not Authorized Execution".to_string())
@@ -190,6 +603,64 @@ fn remove_queue_method(hash: String) -> Result<bool, String> {
     }
 }
 
+#[update(name = "set_approvers", guard = "owner_guard")]
+#[candid_method(update, rename = "set_approvers")]
+async fn set_approvers(approvers: Vec<Principal>, threshold: u32) -> Result<(), String> {
+    WalletService::set_approvers(approvers, threshold)
+}
+
+#[update(name = "set_key_operation_threshold", guard = "owner_guard")]
+#[candid_method(update, rename = "set_key_operation_threshold")]
+async fn set_key_operation_threshold(threshold: Option<u32>) {
+    WalletService::set_key_operation_threshold(threshold)
+}
+
+/// What the caller may still send on `ledger` under their token policy.
+#[query(name = "get_token_allowance", guard = "owner_or_valid_user_guard")]
+#[candid_method(query, rename = "get_token_allowance")]
+fn get_token_allowance(ledger: Principal) -> Option<TokenAllowance> {
+    let session = WalletService::get_expiry_user(&caller())?;
+    token::allowance(&session, &ledger, ic_cdk::api::time())
+}
+
+/// ICRC-2 allowances the wallet granted that are still outstanding.
+#[query(name = "list_approvals", guard = "owner_guard")]
+#[candid_method(query, rename = "list_approvals")]
+fn list_approvals() -> Vec<Approval> {
+    WalletService::list_approvals()
+}
+
+/// Sets every outstanding allowance back to zero.
+#[update(name = "revoke_approvals", guard = "owner_guard")]
+#[candid_method(update, rename = "revoke_approvals")]
+async fn revoke_approvals() -> Vec<ApprovalRevocation> {
+    wallet_canister_mod::revoke_approvals(caller()).await
+}
+
+#[update(name = "set_rate_limit", guard = "owner_guard")]
+#[candid_method(update, rename = "set_rate_limit")]
+async fn set_rate_limit(limit: Option<RateLimit>) {
+    WalletService::set_rate_limit(limit)
+}
+
+#[query(name = "get_rate_counters", guard = "owner_guard")]
+#[candid_method(query, rename = "get_rate_counters")]
+fn get_rate_counters() -> Vec<RateCounter> {
+    WalletService::list_rate_counters(ic_cdk::api::time())
+}
+
+#[update(name = "set_queue_approval_period", guard = "owner_guard")]
+#[candid_method(update, rename = "set_queue_approval_period")]
+async fn set_queue_approval_period(period: u64) {
+    WalletService::set_queue_approval_period(period)
+}
+
+#[update(name = "set_queue_retention_period", guard = "owner_guard")]
+#[candid_method(update, rename = "set_queue_retention_period")]
+async fn set_queue_retention_period(period: u64) {
+    WalletService::set_queue_retention_period(period)
+}
+
 #[update(name = "set_method_validate_type", guard = "owner_guard")]
 #[candid_method(update, rename = "set_method_validate_type")]
 async fn set_method_validate_type(validate_type: MethodValidationType) {
@@ -202,6 +673,34 @@ async fn add_expiry_user(user: Principal, targets: ProxyActorTargets) -> ExpiryU
     WalletService::add_expiry_user(user, targets)
 }
 
+#[update(name = "extend_expiry_user", guard = "owner_guard")]
+#[candid_method(update, rename = "extend_expiry_user")]
+async fn extend_expiry_user(user: Principal, period: u64) -> Option<ExpiryUser> {
+    WalletService::extend_expiry_user(&user, period)
+}
+
+#[update(name = "shorten_expiry_user", guard = "owner_guard")]
+#[candid_method(update, rename = "shorten_expiry_user")]
+async fn shorten_expiry_user(user: Principal, period: u64) -> Option<ExpiryUser> {
+    WalletService::shorten_expiry_user(&user, period)
+}
+
+#[update(name = "revoke_expiry_user", guard = "owner_guard")]
+#[candid_method(update, rename = "revoke_expiry_user")]
+async fn revoke_expiry_user(user: Principal) -> Option<ExpiryUser> {
+    WalletService::remove_expiry_user(&user)
+}
+
+#[query(name = "get_expiry_user", guard = "owner_or_valid_user_guard")]
+#[candid_method(query, rename = "get_expiry_user")]
+fn get_expiry_user(user: Principal) -> Option<ExpiryUser> {
+    let caller = caller();
+    if !is_owner(caller) && caller != user {
+        return None;
+    }
+    WalletService::get_expiry_user(&user).filter(|u| !u.is_expired(ic_cdk::api::time()))
+}
+
 #[update(name = "set_expiry_period", guard = "owner_guard")]
 #[candid_method(update, rename = "set_expiry_period")]
 async fn set_expiry_period(secs: u64) {
@@ -220,6 +719,21 @@ async fn remove_proxy_black_list(target: Principal) -> Option<String> {
     WalletService::remove_proxy_black_list(&target)
 }
 
+/// The wallet's ICP ledger account identifier, as hex, for `subaccount` or
+/// the default subaccount.
+#[query(name = "get_account_identifier")]
+#[candid_method(query, rename = "get_account_identifier")]
+fn get_account_identifier(subaccount: Option<Vec<u8>>) -> Result<String, String> {
+    if subaccount.as_ref().is_some_and(|s| s.len() != 32) {
+        return Err("A subaccount is 32 bytes".to_string());
+    }
+    let account = Account {
+        owner: ic_cdk::api::id(),
+        subaccount: subaccount.map(ByteBuf::from),
+    };
+    Ok(hex::encode(token::account_identifier(&account)))
+}
+
 #[query(name = "is_proxy_black_list")]
 #[candid_method(query, rename = "is_proxy_black_list")]
 async fn is_proxy_black_list(target: Principal) -> bool {
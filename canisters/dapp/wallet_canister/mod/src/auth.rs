@@ -0,0 +1,340 @@
+use crate::nft;
+use crate::policy;
+use crate::service::WalletService;
+use crate::token;
+use crate::types::{
+    CallCanisterArgs, Decision, Denial, ExpiryUser, Method, MethodType, MethodValidationType,
+    RateKey, RateLimit,
+};
+use ic_cdk::api;
+use ic_cdk::export::Principal;
+use std::borrow::Cow;
+
+/// Decides what happens to a call `caller` makes at `now`, without changing any state.
+pub fn check_call(
+    caller: &Principal,
+    is_owner: bool,
+    args: &CallCanisterArgs<u128>,
+    now: u64,
+) -> Decision {
+    if is_owner {
+        return Decision::Execute;
+    }
+    match check_delegate_call(caller, args, now, false) {
+        Ok(decision) => decision,
+        Err(denial) => Decision::Deny(denial),
+    }
+}
+
+/// `check_call` for `proxy_query`. Delegates may only query methods registered
+/// as `QUERY` or `CompositeQuery`, or granted by `all_queries`, and since a
+/// query can't queue anything or move cycles, calls that need approval or
+/// attach cycles are denied.
+pub fn check_query(
+    caller: &Principal,
+    is_owner: bool,
+    args: &CallCanisterArgs<u128>,
+    now: u64,
+) -> Result<(), Denial> {
+    if args.cycles > 0 {
+        return Err(Denial::BudgetExceeded {
+            requested: args.cycles,
+            available: 0,
+        });
+    }
+    if is_owner {
+        return Ok(());
+    }
+    match check_delegate_call(caller, args, now, true)? {
+        Decision::Queue => Err(Denial::ApprovalRequired),
+        _ => Ok(()),
+    }
+}
+
+/// Acts on `check_call`: drops an expired session, queues a call that needs
+/// approval and reserves the cycles, token allowance and NFT transfers of a
+/// call that runs now. Returns the queue id and deadline of a queued call.
+pub fn authorize(
+    caller: &Principal,
+    is_owner: bool,
+    args: CallCanisterArgs<u128>,
+) -> Result<Option<(String, Option<u64>)>, Denial> {
+    match check_call(caller, is_owner, &args, api::time()) {
+        Decision::Execute => charge(caller, is_owner, &args).map(|_| None),
+        Decision::Queue => {
+            record_call(caller, is_owner, &args);
+            let obj = WalletService::hash_method(caller, args);
+            let expires_at = obj.expires_at;
+            Ok(Some((WalletService::add_method_queue(obj), expires_at)))
+        }
+        Decision::Deny(denial) => {
+            if let Denial::SessionExpired { .. } = denial {
+                WalletService::remove_expiry_user(caller);
+            }
+            Err(denial)
+        }
+    }
+}
+
+/// `authorize` for the calls of a batch, all of them before any runs. Each call
+/// is checked with what the calls before it were charged, so a batch let
+/// through can't run out of budget, rate or allowance halfway. When a call is
+/// denied nothing stays charged and its index comes back with the denial. When
+/// one needs approval the calls only count against the rate limits, since the
+/// batch is queued as one unit.
+pub fn authorize_batch(
+    caller: &Principal,
+    is_owner: bool,
+    calls: &[CallCanisterArgs<u128>],
+) -> Result<Decision, (usize, Denial)> {
+    let mut decision = Decision::Execute;
+    let mut charged: Vec<(&CallCanisterArgs<u128>, bool)> = vec![];
+    for (index, args) in calls.iter().enumerate() {
+        let outcome = match check_call(caller, is_owner, args, api::time()) {
+            Decision::Execute => charge(caller, is_owner, args).map(|_| true),
+            Decision::Queue => {
+                decision = Decision::Queue;
+                record_call(caller, is_owner, args);
+                Ok(false)
+            }
+            Decision::Deny(denial) => Err(denial),
+        };
+        match outcome {
+            Ok(runs) => charged.push((args, runs)),
+            Err(denial) => {
+                for (args, runs) in charged {
+                    if runs {
+                        release(caller, is_owner, args, false);
+                    } else {
+                        forget_call(caller, is_owner, args);
+                    }
+                }
+                if let Denial::SessionExpired { .. } = denial {
+                    WalletService::remove_expiry_user(caller);
+                }
+                return Err((index, denial));
+            }
+        }
+    }
+    if decision == Decision::Queue {
+        for (args, runs) in charged {
+            if runs {
+                release(caller, is_owner, args, true);
+            }
+        }
+    }
+    Ok(decision)
+}
+
+/// Decides again on a call of a batch let through by `authorize_batch` once
+/// its arguments are taken from an earlier reply. What was charged for it with
+/// the arguments it was sent with is given back first, and a call that may run
+/// now is charged anew. A call that needs approval is not counted yet.
+pub fn reauthorize(
+    caller: &Principal,
+    is_owner: bool,
+    charged: &CallCanisterArgs<u128>,
+    args: &CallCanisterArgs<u128>,
+) -> Decision {
+    release(caller, is_owner, charged, false);
+    match check_call(caller, is_owner, args, api::time()) {
+        Decision::Execute => match charge(caller, is_owner, args) {
+            Ok(()) => Decision::Execute,
+            Err(denial) => Decision::Deny(denial),
+        },
+        Decision::Deny(denial) => {
+            if let Denial::SessionExpired { .. } = denial {
+                WalletService::remove_expiry_user(caller);
+            }
+            Decision::Deny(denial)
+        }
+        decision => decision,
+    }
+}
+
+/// Gives back what `charge` took for a call that won't run. A call that is
+/// queued instead keeps counting against the rate limits.
+pub fn release(caller: &Principal, is_owner: bool, args: &CallCanisterArgs<u128>, queued: bool) {
+    if !is_owner {
+        WalletService::refund_cycles(caller, &args.canister, &args.method_name, args.cycles);
+        token::release_transfer(caller, args);
+        nft::release_transfers(caller, args, None);
+    }
+    if !queued {
+        forget_call(caller, is_owner, args);
+    }
+}
+
+/// Charges a call that runs now against the caller's cycles budget, rate
+/// limits, token allowance and NFT transfers.
+fn charge(caller: &Principal, is_owner: bool, args: &CallCanisterArgs<u128>) -> Result<(), Denial> {
+    if !is_owner && !WalletService::reserve_cycles(caller, args) {
+        return Err(Denial::BudgetExceeded {
+            requested: args.cycles,
+            available: 0,
+        });
+    }
+    record_call(caller, is_owner, args);
+    if !is_owner {
+        token::record_transfer(caller, args, api::time());
+        nft::record_transfers(caller, args);
+    }
+    Ok(())
+}
+
+fn check_delegate_call(
+    caller: &Principal,
+    args: &CallCanisterArgs<u128>,
+    now: u64,
+    query: bool,
+) -> Result<Decision, Denial> {
+    let session = WalletService::get_expiry_user(caller).ok_or(Denial::NotDelegate)?;
+    if session.is_expired(now) {
+        return Err(Denial::SessionExpired {
+            expired_at: session.expiry_timestamp,
+        });
+    }
+    if WalletService::is_proxy_black_list(&args.canister) {
+        return Err(Denial::CanisterBlacklisted);
+    }
+    if !session
+        .target_list
+        .iter()
+        .any(|d| d.canister.eq(&args.canister))
+    {
+        return Err(Denial::CanisterNotAllowed);
+    }
+    let method = if query {
+        session.query_method(&args.canister, &args.method_name)
+    } else {
+        session
+            .method(&args.canister, &args.method_name)
+            .map(Cow::Borrowed)
+    }
+    .ok_or(Denial::MethodNotAllowed)?;
+    if query
+        && !matches!(
+            method.method_type,
+            MethodType::QUERY | MethodType::CompositeQuery
+        )
+    {
+        return Err(Denial::NotAQuery);
+    }
+    let nft_approval = check_args(&session, &method, args, now)?;
+    for (key, limit) in rate_limits(&session, args) {
+        WalletService::check_rate(&key, &limit, now)?;
+    }
+    if nft_approval || needs_approval(&method) {
+        return Ok(Decision::Queue);
+    }
+    check_cycles(&session, &method, args.cycles)?;
+    Ok(Decision::Execute)
+}
+
+/// Checks the arguments of a call of an approved batch that were taken from
+/// an earlier reply, since they weren't known when the batch was queued.
+pub fn check_resolved_args(
+    user: &Principal,
+    args: &CallCanisterArgs<u128>,
+    now: u64,
+) -> Result<(), Denial> {
+    let session = WalletService::get_expiry_user(user).ok_or(Denial::NotDelegate)?;
+    let method = session
+        .method(&args.canister, &args.method_name)
+        .ok_or(Denial::MethodNotAllowed)?;
+    check_args(&session, method, args, now).map(|_| ())
+}
+
+/// Checks `args` against the method's `ArgPolicy` and the target's token and
+/// NFT policies. Returns whether the NFT policy wants the call approved.
+fn check_args(
+    session: &ExpiryUser,
+    method: &Method,
+    args: &CallCanisterArgs<u128>,
+    now: u64,
+) -> Result<bool, Denial> {
+    if let Some(arg_policy) = &method.arg_policy {
+        policy::check_args(arg_policy, &args.args)
+            .map_err(|reason| Denial::ArgumentRejected { reason })?;
+    }
+    token::check_token_call(session, args, now)?;
+    nft::check_nft_call(session, args)
+}
+
+/// The limits a delegate call counts against: the wallet's, the session's,
+/// the target canister's and the method's, where set.
+fn rate_limits(session: &ExpiryUser, args: &CallCanisterArgs<u128>) -> Vec<(RateKey, RateLimit)> {
+    let target = session
+        .target_list
+        .iter()
+        .find(|d| d.canister.eq(&args.canister));
+    let method = session.method_entry(&args.canister, &args.method_name);
+    [
+        (RateKey::Wallet, WalletService::get_rate_limit()),
+        (RateKey::Delegate(session.user), session.rate_limit.clone()),
+        (
+            RateKey::Target(session.user, args.canister),
+            target.and_then(|d| d.rate_limit.clone()),
+        ),
+    ]
+    .into_iter()
+    .chain(method.map(|(key, m)| {
+        (
+            RateKey::Method(session.user, args.canister, key.to_string()),
+            m.rate_limit.clone(),
+        )
+    }))
+    .filter_map(|(key, limit)| Some((key, limit?)))
+    .collect()
+}
+
+/// Counts a call that was let through against its rate limits. Queued calls
+/// count too, so queueing can't be used to get around a limit.
+pub fn record_call(caller: &Principal, is_owner: bool, args: &CallCanisterArgs<u128>) {
+    if is_owner {
+        return;
+    }
+    if let Some(session) = WalletService::get_expiry_user(caller) {
+        let keys = rate_limits(&session, args).into_iter().map(|(key, _)| key);
+        WalletService::record_call(keys, api::time());
+    }
+}
+
+/// Takes back `record_call` for a call that neither ran nor was queued.
+fn forget_call(caller: &Principal, is_owner: bool, args: &CallCanisterArgs<u128>) {
+    if is_owner {
+        return;
+    }
+    if let Some(session) = WalletService::get_expiry_user(caller) {
+        let keys = rate_limits(&session, args).into_iter().map(|(key, _)| key);
+        WalletService::forget_call(keys, api::time());
+    }
+}
+
+fn needs_approval(method: &Method) -> bool {
+    match WalletService::get_method_validate_type() {
+        MethodValidationType::ALL => true,
+        MethodValidationType::UPDATE => method.method_type == MethodType::CALL,
+        MethodValidationType::KEY => method.key_operation,
+    }
+}
+
+/// Same limits `WalletService::reserve_cycles` charges against.
+fn check_cycles(session: &ExpiryUser, method: &Method, cycles: u128) -> Result<(), Denial> {
+    if cycles == 0 {
+        return Ok(());
+    }
+    let available = session.cycles.as_ref().map_or(0, |b| b.available());
+    let available = method
+        .cycles
+        .as_ref()
+        .map_or(available, |b| b.available().min(available));
+    if cycles <= available {
+        Ok(())
+    } else {
+        Err(Denial::BudgetExceeded {
+            requested: cycles,
+            available,
+        })
+    }
+}
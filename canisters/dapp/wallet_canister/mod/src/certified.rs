@@ -0,0 +1,170 @@
+use crate::memory;
+use crate::merkle::{Trie, Witness};
+use crate::service::{WalletService, AUDIT_LOG, CALL_QUEUE};
+use crate::types::{AuditEntry, CertifiedData, MethodQueueItem};
+use ic_cdk::api;
+use ic_certified_map::{fork_hash, labeled_hash, Hash, HashTree};
+use ic_stable_structures::Storable;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::ops::Bound;
+
+const AUDIT_LABEL: &[u8] = b"audit";
+const QUEUE_LABEL: &[u8] = b"queue";
+
+/// Length of a queue id, the hex of a SHA-256.
+const QUEUE_KEY_LEN: usize = 64;
+
+/// Entries and queued calls `backfill` certifies in one go.
+const BACKFILL_BATCH: usize = 200;
+
+thread_local! {
+    /// Hashes of every audit entry, keyed by big-endian id.
+    static AUDIT_TRIE: RefCell<Trie> =
+        RefCell::new(Trie::init(memory::get_audit_trie_memory(), 8));
+
+    /// Hashes of every queued call, keyed by queue id.
+    static QUEUE_TRIE: RefCell<Trie> =
+        RefCell::new(Trie::init(memory::get_queue_trie_memory(), QUEUE_KEY_LEN));
+}
+
+/// Certifies the root of both tries. They live in stable memory, so this is
+/// all `init` and `post_upgrade` need to do.
+pub fn certify_root() {
+    api::set_certified_data(&root_hash());
+}
+
+/// Certifies the next batch of what was stored before an upgrade to stable
+/// certification. Returns whether there is more to certify.
+pub fn backfill() -> bool {
+    let mut progress = match WalletService::get_certification_backfill() {
+        None => return false,
+        Some(progress) => progress,
+    };
+    let mut budget = BACKFILL_BATCH;
+    let audit_len = AUDIT_LOG.with(|l| {
+        let log = l.borrow();
+        while budget > 0 && progress.audit_next < log.len() {
+            if let Some(entry) = log.get(progress.audit_next) {
+                insert_audit_entry(&entry);
+            }
+            progress.audit_next += 1;
+            budget -= 1;
+        }
+        log.len()
+    });
+    let items = CALL_QUEUE.with(|m| {
+        let queue = m.borrow();
+        let range = match progress.queue_after.clone() {
+            None => queue.range(..),
+            Some(after) => queue.range((Bound::Excluded(after), Bound::Unbounded)),
+        };
+        range.take(budget).collect::<Vec<_>>()
+    });
+    let queue_done = items.len() < budget;
+    for (hash, item) in items {
+        insert_queue_item(&item);
+        progress.queue_after = Some(hash);
+    }
+    certify_root();
+    let done = progress.audit_next >= audit_len && queue_done;
+    WalletService::set_certification_backfill((!done).then_some(progress));
+    !done
+}
+
+pub fn certify_audit_entry(entry: &AuditEntry) {
+    // entries the backfill has yet to reach are certified in order by it
+    if WalletService::get_certification_backfill().is_some_and(|b| entry.id >= b.audit_next) {
+        return;
+    }
+    insert_audit_entry(entry);
+    certify_root();
+}
+
+pub fn certify_queue_item(item: &MethodQueueItem<u128>) {
+    insert_queue_item(item);
+    certify_root();
+}
+
+pub fn uncertify_queue_item(hash: &str) {
+    QUEUE_TRIE.with(|t| t.borrow_mut().remove(hash.as_bytes()));
+    certify_root();
+}
+
+/// The queued call `hash` with a witness of its presence, or of its absence.
+pub fn queue_item(hash: &str) -> CertifiedData {
+    let values = CALL_QUEUE
+        .with(|m| m.borrow().get(&hash.to_string()))
+        .map(|item| item.to_bytes().into_owned())
+        .into_iter()
+        .collect();
+    let queue = QUEUE_TRIE.with(|t| t.borrow().witness(hash.as_bytes(), hash.as_bytes()));
+    let audit = AUDIT_TRIE.with(|t| t.borrow().root_hash());
+    let witness = Witness::Fork(
+        Box::new(Witness::Pruned(labeled_hash(AUDIT_LABEL, &audit))),
+        Box::new(Witness::Labeled(QUEUE_LABEL.to_vec(), Box::new(queue))),
+    );
+    certified(values, &witness)
+}
+
+/// Up to `limit` audit entries from id `from` on, with a witness of that range.
+pub fn audit_entries(from: u64, limit: u64) -> CertifiedData {
+    let values = AUDIT_LOG.with(|l| {
+        let log = l.borrow();
+        (from..log.len().min(from.saturating_add(limit)))
+            .filter_map(|id| log.get(id))
+            .map(|entry| entry.to_bytes().into_owned())
+            .collect()
+    });
+    let last = from.saturating_add(limit.max(1) - 1);
+    let audit = AUDIT_TRIE.with(|t| t.borrow().witness(&audit_key(from), &audit_key(last)));
+    let queue = QUEUE_TRIE.with(|t| t.borrow().root_hash());
+    let witness = Witness::Fork(
+        Box::new(Witness::Labeled(AUDIT_LABEL.to_vec(), Box::new(audit))),
+        Box::new(Witness::Pruned(labeled_hash(QUEUE_LABEL, &queue))),
+    );
+    certified(values, &witness)
+}
+
+fn insert_audit_entry(entry: &AuditEntry) {
+    let key = audit_key(entry.id);
+    AUDIT_TRIE.with(|t| t.borrow_mut().insert(&key, &key, leaf(&entry.to_bytes())));
+}
+
+fn insert_queue_item(item: &MethodQueueItem<u128>) {
+    let key = item.hash.as_bytes();
+    QUEUE_TRIE.with(|t| t.borrow_mut().insert(key, key, leaf(&item.to_bytes())));
+}
+
+fn certified(values: Vec<Vec<u8>>, witness: &Witness) -> CertifiedData {
+    CertifiedData {
+        values,
+        witness: serialize(&witness.as_hash_tree()),
+        certificate: api::data_certificate(),
+    }
+}
+
+fn root_hash() -> Hash {
+    let audit = AUDIT_TRIE.with(|t| t.borrow().root_hash());
+    let queue = QUEUE_TRIE.with(|t| t.borrow().root_hash());
+    fork_hash(
+        &labeled_hash(AUDIT_LABEL, &audit),
+        &labeled_hash(QUEUE_LABEL, &queue),
+    )
+}
+
+fn audit_key(id: u64) -> [u8; 8] {
+    id.to_be_bytes()
+}
+
+fn leaf(bytes: &[u8]) -> Hash {
+    Sha256::digest(bytes).into()
+}
+
+fn serialize(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer).unwrap();
+    serializer.into_inner()
+}
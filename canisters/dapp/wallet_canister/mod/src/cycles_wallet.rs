@@ -0,0 +1,100 @@
+use crate::describe_error;
+use crate::types::{
+    CallCanisterArgs, CallResult, CreateCanisterArgs, CreateResult, ProxyCallResult,
+};
+use candid::{CandidType, Decode, Encode, Nat};
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::export::Principal;
+use serde::Deserialize;
+
+/// Methods of the management canister the cycles wallet endpoints call. A
+/// delegate needs them among its targets like any other method.
+pub const DEPOSIT_CYCLES: &str = "deposit_cycles";
+pub const CREATE_CANISTER: &str = "create_canister";
+
+#[derive(CandidType, Deserialize)]
+struct CanisterIdRecord {
+    canister_id: Principal,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ManagementCanisterSettings {
+    controllers: Option<Vec<Principal>>,
+    compute_allocation: Option<Nat>,
+    memory_allocation: Option<Nat>,
+    freezing_threshold: Option<Nat>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct CreateCanisterArgument {
+    settings: Option<ManagementCanisterSettings>,
+}
+
+/// The call `wallet_send` makes: `amount` cycles deposited into `canister`.
+pub fn deposit_cycles_call(canister: Principal, amount: u128) -> CallCanisterArgs<u128> {
+    CallCanisterArgs {
+        canister: Principal::management_canister(),
+        method_name: DEPOSIT_CYCLES.to_string(),
+        args: Encode!(&CanisterIdRecord {
+            canister_id: canister
+        })
+        .expect("deposit_cycles arguments encode"),
+        cycles: amount,
+    }
+}
+
+/// The call `wallet_create_canister` makes. Without controllers the new
+/// canister is controlled by the wallet, as the management canister does.
+pub fn create_canister_call(args: CreateCanisterArgs<u128>) -> CallCanisterArgs<u128> {
+    let settings = args.settings;
+    let controllers = settings
+        .controllers
+        .or_else(|| settings.controller.map(|c| vec![c]));
+    let arg = CreateCanisterArgument {
+        settings: Some(ManagementCanisterSettings {
+            controllers,
+            compute_allocation: settings.compute_allocation,
+            memory_allocation: settings.memory_allocation,
+            freezing_threshold: settings.freezing_threshold,
+        }),
+    };
+    CallCanisterArgs {
+        canister: Principal::management_canister(),
+        method_name: CREATE_CANISTER.to_string(),
+        args: Encode!(&arg).expect("create_canister arguments encode"),
+        cycles: args.cycles,
+    }
+}
+
+/// What the cycles wallet's `wallet_call` returns for the outcome of `proxy_call`.
+/// Tools that expect a reply can't wait for approval or take a one-way call,
+/// so those are errors that name the queue or audit id.
+pub fn call_result(result: ProxyCallResult) -> Result<CallResult, String> {
+    match result {
+        ProxyCallResult::Executed(reply) => Ok(reply),
+        ProxyCallResult::Queued { id, .. } => {
+            Err(format!("The call needs approval and was queued as {}", id))
+        }
+        ProxyCallResult::Rejected {
+            reject_code,
+            message,
+        } => Err(describe_error((
+            RejectionCode::from(reject_code as i32),
+            message,
+        ))),
+        ProxyCallResult::Unauthorized { reason } => Err(reason.to_string()),
+        ProxyCallResult::Submitted { id } => Err(format!(
+            "The call was sent one-way as audit entry {} and has no reply",
+            id
+        )),
+    }
+}
+
+pub fn create_result(result: ProxyCallResult) -> Result<CreateResult, String> {
+    let reply = call_result(result)?;
+    Decode!(&reply.r#return, CanisterIdRecord)
+        .map(|r| CreateResult {
+            canister_id: r.canister_id,
+        })
+        .map_err(|e| format!("Invalid create_canister reply: {}", e))
+}
@@ -0,0 +1,85 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _};
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const UPGRADES: MemoryId = MemoryId::new(0);
+const EXPIRY_USERS: MemoryId = MemoryId::new(1);
+const CALL_QUEUE: MemoryId = MemoryId::new(2);
+const AUDIT_LOG_INDEX: MemoryId = MemoryId::new(3);
+const AUDIT_LOG_DATA: MemoryId = MemoryId::new(4);
+const AUDIT_TRIE: MemoryId = MemoryId::new(5);
+const QUEUE_TRIE: MemoryId = MemoryId::new(6);
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+pub fn get_expiry_users_memory() -> Memory {
+    get_memory(EXPIRY_USERS)
+}
+
+pub fn get_call_queue_memory() -> Memory {
+    get_memory(CALL_QUEUE)
+}
+
+pub fn get_audit_log_index_memory() -> Memory {
+    get_memory(AUDIT_LOG_INDEX)
+}
+
+pub fn get_audit_log_data_memory() -> Memory {
+    get_memory(AUDIT_LOG_DATA)
+}
+
+pub fn get_audit_trie_memory() -> Memory {
+    get_memory(AUDIT_TRIE)
+}
+
+pub fn get_queue_trie_memory() -> Memory {
+    get_memory(QUEUE_TRIE)
+}
+
+/// Whether stable memory still holds a snapshot written by `ic_cdk::storage::stable_save`,
+/// the layout used before the memory manager. Must run before any memory is requested.
+pub fn has_raw_candid_state() -> bool {
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        return false;
+    }
+    let mut magic = [0u8; 4];
+    ic_cdk::api::stable::stable64_read(0, &mut magic);
+    &magic == b"DIDL"
+}
+
+/// Writes the upgrade snapshot, prefixed with its length, to the upgrades memory.
+pub fn save_upgrade_bytes(bytes: &[u8]) {
+    let memory = get_memory(UPGRADES);
+    let required = 8 + bytes.len() as u64;
+    let pages = memory.size();
+    if pages * WASM_PAGE_SIZE < required {
+        let grown = memory.grow((required - pages * WASM_PAGE_SIZE).div_ceil(WASM_PAGE_SIZE));
+        assert!(grown != -1, "failed to grow the upgrades memory");
+    }
+    memory.write(0, &(bytes.len() as u64).to_le_bytes());
+    memory.write(8, bytes);
+}
+
+/// Reads the snapshot written by `save_upgrade_bytes`, if there is one.
+pub fn load_upgrade_bytes() -> Option<Vec<u8>> {
+    let memory = get_memory(UPGRADES);
+    if memory.size() == 0 {
+        return None;
+    }
+    let mut len = [0u8; 8];
+    memory.read(0, &mut len);
+    let mut bytes = vec![0u8; u64::from_le_bytes(len) as usize];
+    memory.read(8, &mut bytes);
+    Some(bytes)
+}
@@ -0,0 +1,367 @@
+use crate::memory::Memory;
+use candid::{CandidType, Decode, Encode};
+use ic_certified_map::{fork_hash, labeled_hash, leaf_hash, Hash, HashTree};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use std::borrow::Cow;
+
+/// Longest key a trie takes, in bytes.
+pub const MAX_KEY_LEN: usize = 64;
+
+/// A node of a trie: the bits of its key up to `depth`, the rest zeroed. A
+/// leaf has the whole key, a branch the bits its two subtrees share.
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NodeId {
+    depth: u16,
+    prefix: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+enum NodeKind {
+    Branch([Option<NodeId>; 2]),
+    /// A value under its label, both as they appear in a witness.
+    Leaf {
+        label: ByteBuf,
+        value: ByteBuf,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct Node {
+    hash: ByteBuf,
+    kind: NodeKind,
+}
+
+impl Storable for NodeId {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.depth.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.prefix);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        NodeId {
+            depth: u16::from_be_bytes([bytes[0], bytes[1]]),
+            prefix: ByteBuf::from(bytes[2..].to_vec()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 2 + MAX_KEY_LEN as u32,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for Node {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// An owned `HashTree`, built from stable memory before it is serialized.
+pub enum Witness {
+    Empty,
+    Fork(Box<Witness>, Box<Witness>),
+    Labeled(Vec<u8>, Box<Witness>),
+    Leaf(Vec<u8>),
+    Pruned(Hash),
+}
+
+impl Witness {
+    pub fn as_hash_tree(&self) -> HashTree<'_> {
+        match self {
+            Witness::Empty => HashTree::Empty,
+            Witness::Fork(l, r) => HashTree::Fork(Box::new((l.as_hash_tree(), r.as_hash_tree()))),
+            Witness::Labeled(label, t) => HashTree::Labeled(label, Box::new(t.as_hash_tree())),
+            Witness::Leaf(value) => HashTree::Leaf(Cow::Borrowed(value)),
+            Witness::Pruned(hash) => HashTree::Pruned(*hash),
+        }
+    }
+}
+
+/// A binary Patricia trie over fixed-length keys, kept in stable memory so
+/// that its root hash survives upgrades without being recomputed. Updates
+/// touch only the nodes on the path to a key.
+///
+/// Its hash is that of an IC hash tree: a branch is a fork of its subtrees, a
+/// leaf its value labeled with its label, and the root an `Empty` tree while
+/// the trie is. Subtrees are ordered by key, so a witness can prove that a key
+/// is absent by showing its neighbours.
+pub struct Trie {
+    nodes: StableBTreeMap<NodeId, Node, Memory>,
+    key_len: usize,
+}
+
+impl Trie {
+    pub fn init(memory: Memory, key_len: usize) -> Self {
+        assert!(key_len <= MAX_KEY_LEN);
+        Trie {
+            nodes: StableBTreeMap::init(memory),
+            key_len,
+        }
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        self.nodes
+            .get(&self.root())
+            .map_or_else(empty_hash, |node| hash_of(&node))
+    }
+
+    /// Stores `value` under `key`, replacing the value it had.
+    pub fn insert(&mut self, key: &[u8], label: &[u8], value: Hash) {
+        let leaf = self.leaf(key);
+        self.nodes.insert(
+            leaf.clone(),
+            Node {
+                hash: ByteBuf::from(labeled_hash(label, &leaf_hash(&value)).to_vec()),
+                kind: NodeKind::Leaf {
+                    label: ByteBuf::from(label.to_vec()),
+                    value: ByteBuf::from(value.to_vec()),
+                },
+            },
+        );
+        let mut path = vec![];
+        let mut at = self.root();
+        let mut node = self.nodes.get(&at).unwrap_or(Node {
+            hash: ByteBuf::new(),
+            kind: NodeKind::Branch([None, None]),
+        });
+        loop {
+            let side = bit(&leaf.prefix, at.depth);
+            let child = match &node.kind {
+                NodeKind::Branch(children) => children[side].clone(),
+                NodeKind::Leaf { .. } => unreachable!("a leaf has no subtrees"),
+            };
+            match child {
+                Some(child) if child != leaf && is_prefix(&child, &leaf.prefix) => {
+                    path.push(at);
+                    node = self.nodes.get(&child).expect("a subtree of the trie");
+                    at = child;
+                }
+                child => {
+                    let subtree = match child {
+                        // keys that share the child's prefix part from it further down
+                        Some(child) if child != leaf => {
+                            let depth = common_bits(&child.prefix, &leaf.prefix, child.depth);
+                            let branch = NodeId {
+                                depth,
+                                prefix: mask(&leaf.prefix, depth),
+                            };
+                            let mut children = [None, None];
+                            children[bit(&leaf.prefix, depth)] = Some(leaf.clone());
+                            let child_side = bit(&child.prefix, depth);
+                            children[child_side] = Some(child);
+                            self.nodes.insert(
+                                branch.clone(),
+                                Node {
+                                    hash: ByteBuf::new(),
+                                    kind: NodeKind::Branch(children),
+                                },
+                            );
+                            self.rehash(&branch);
+                            branch
+                        }
+                        _ => leaf.clone(),
+                    };
+                    if let NodeKind::Branch(children) = &mut node.kind {
+                        children[side] = Some(subtree);
+                    }
+                    self.nodes.insert(at.clone(), node);
+                    path.push(at);
+                    break;
+                }
+            }
+        }
+        for id in path.iter().rev() {
+            self.rehash(id);
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        let leaf = self.leaf(key);
+        if self.nodes.remove(&leaf).is_none() {
+            return;
+        }
+        let mut path = vec![];
+        let mut at = self.root();
+        loop {
+            let mut node = self.nodes.get(&at).expect("a subtree of the trie");
+            let side = bit(&leaf.prefix, at.depth);
+            let children = match &mut node.kind {
+                NodeKind::Branch(children) => children,
+                NodeKind::Leaf { .. } => unreachable!("a leaf has no subtrees"),
+            };
+            if children[side].as_ref() != Some(&leaf) {
+                path.push(at);
+                at = children[side].clone().expect("a path to a stored leaf");
+                continue;
+            }
+            children[side] = None;
+            match path.last() {
+                // the root is the only branch that may miss a subtree
+                None if children.iter().all(Option::is_none) => {
+                    self.nodes.remove(&at);
+                }
+                None => {
+                    self.nodes.insert(at.clone(), node);
+                    path.push(at);
+                }
+                // a branch left with one subtree is replaced by it
+                Some(parent) => {
+                    let sibling = children[1 - side].take();
+                    self.nodes.remove(&at);
+                    let mut parent_node = self.nodes.get(parent).expect("a subtree of the trie");
+                    if let NodeKind::Branch(children) = &mut parent_node.kind {
+                        children[bit(&leaf.prefix, parent.depth)] = sibling;
+                    }
+                    self.nodes.insert(parent.clone(), parent_node);
+                }
+            }
+            break;
+        }
+        for id in path.iter().rev() {
+            self.rehash(id);
+        }
+    }
+
+    /// A witness of the values with keys from `lo` to `hi`, and of the keys
+    /// just outside that range, which prove there are no others in it.
+    pub fn witness(&self, lo: &[u8], hi: &[u8]) -> Witness {
+        let lo = self.leaf(lo).prefix.into_vec();
+        let hi = self.leaf(hi).prefix.into_vec();
+        let root = self.root();
+        if !self.nodes.contains_key(&root) {
+            return Witness::Empty;
+        }
+        let from = self.last_below(&root, &lo).unwrap_or(lo);
+        let to = self.first_above(&root, &hi).unwrap_or(hi);
+        self.build(&root, &from, &to)
+    }
+
+    fn build(&self, at: &NodeId, lo: &[u8], hi: &[u8]) -> Witness {
+        let node = self.nodes.get(at).expect("a subtree of the trie");
+        if self.max_key(at).as_slice() < lo || at.prefix.as_slice() > hi {
+            return Witness::Pruned(hash_of(&node));
+        }
+        match node.kind {
+            NodeKind::Leaf { label, value } => {
+                Witness::Labeled(label.into_vec(), Box::new(Witness::Leaf(value.into_vec())))
+            }
+            NodeKind::Branch([l, r]) => {
+                let subtree = |child: Option<NodeId>| {
+                    Box::new(child.map_or(Witness::Empty, |c| self.build(&c, lo, hi)))
+                };
+                Witness::Fork(subtree(l), subtree(r))
+            }
+        }
+    }
+
+    /// The greatest key below `bound` under `at`.
+    fn last_below(&self, at: &NodeId, bound: &[u8]) -> Option<Vec<u8>> {
+        if at.prefix.as_slice() >= bound {
+            return None;
+        }
+        match self.nodes.get(at)?.kind {
+            NodeKind::Leaf { .. } => Some(at.prefix.to_vec()),
+            NodeKind::Branch([l, r]) => [r, l]
+                .into_iter()
+                .flatten()
+                .find_map(|c| self.last_below(&c, bound)),
+        }
+    }
+
+    /// The least key above `bound` under `at`.
+    fn first_above(&self, at: &NodeId, bound: &[u8]) -> Option<Vec<u8>> {
+        if self.max_key(at).as_slice() <= bound {
+            return None;
+        }
+        match self.nodes.get(at)?.kind {
+            NodeKind::Leaf { .. } => Some(at.prefix.to_vec()),
+            NodeKind::Branch([l, r]) => [l, r]
+                .into_iter()
+                .flatten()
+                .find_map(|c| self.first_above(&c, bound)),
+        }
+    }
+
+    fn rehash(&mut self, id: &NodeId) {
+        let mut node = self.nodes.get(id).expect("a subtree of the trie");
+        if let NodeKind::Branch(children) = &node.kind {
+            let hashes = children.clone().map(|child| {
+                child.map_or_else(empty_hash, |c| {
+                    hash_of(&self.nodes.get(&c).expect("a subtree of the trie"))
+                })
+            });
+            node.hash = ByteBuf::from(fork_hash(&hashes[0], &hashes[1]).to_vec());
+            self.nodes.insert(id.clone(), node);
+        }
+    }
+
+    fn root(&self) -> NodeId {
+        NodeId {
+            depth: 0,
+            prefix: ByteBuf::from(vec![0; self.key_len]),
+        }
+    }
+
+    /// Keys shorter than the trie's are padded with zeros, longer ones cut.
+    fn leaf(&self, key: &[u8]) -> NodeId {
+        let mut prefix = vec![0; self.key_len];
+        let len = key.len().min(self.key_len);
+        prefix[..len].copy_from_slice(&key[..len]);
+        NodeId {
+            depth: (self.key_len * 8) as u16,
+            prefix: ByteBuf::from(prefix),
+        }
+    }
+
+    /// The greatest key under `id`.
+    fn max_key(&self, id: &NodeId) -> Vec<u8> {
+        let mut key = id.prefix.to_vec();
+        for i in id.depth as usize..self.key_len * 8 {
+            key[i / 8] |= 0x80 >> (i % 8);
+        }
+        key
+    }
+}
+
+fn hash_of(node: &Node) -> Hash {
+    node.hash
+        .as_slice()
+        .try_into()
+        .expect("a node hash is 32 bytes")
+}
+
+fn empty_hash() -> Hash {
+    HashTree::Empty.reconstruct()
+}
+
+fn bit(key: &[u8], i: u16) -> usize {
+    let i = i as usize;
+    ((key[i / 8] >> (7 - i % 8)) & 1) as usize
+}
+
+/// The number of leading bits `a` and `b` share, up to `max`.
+fn common_bits(a: &[u8], b: &[u8], max: u16) -> u16 {
+    (0..max).find(|&i| bit(a, i) != bit(b, i)).unwrap_or(max)
+}
+
+fn is_prefix(id: &NodeId, key: &[u8]) -> bool {
+    common_bits(&id.prefix, key, id.depth) == id.depth
+}
+
+fn mask(key: &[u8], depth: u16) -> ByteBuf {
+    let mut masked = key.to_vec();
+    for i in depth as usize..key.len() * 8 {
+        masked[i / 8] &= !(0x80 >> (i % 8));
+    }
+    ByteBuf::from(masked)
+}
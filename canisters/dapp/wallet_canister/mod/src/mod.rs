@@ -1,19 +1,260 @@
+pub mod auth;
+pub mod certified;
+pub mod cycles_wallet;
+pub mod memory;
+pub mod merkle;
+pub mod nft;
+pub mod policy;
 pub mod service;
+pub mod token;
 pub mod types;
 
-use crate::types::{CallCanisterArgs, CallResult};
-use ic_cdk::{api, caller};
+use crate::service::WalletService;
+use crate::types::{
+    ApprovalRevocation, AuditEntry, AuditOutcome, BatchCall, CallCanisterArgs, CallResult,
+    MethodType,
+};
+use candid::IDLArgs;
+use ic_cdk::api;
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::export::Principal;
+use ic_cdk_timers::TimerId;
+use std::cell::Cell;
+use std::time::Duration;
 
-pub async fn wallet_call(args: CallCanisterArgs<u128>) -> Result<CallResult, String> {
-    if api::id() == caller() {
-        return Err("Attempted to call forward on self. This is not allowed. Call this method via a different custodian.".to_string());
+const QUEUE_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+thread_local! {
+    static SCHEDULER_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+}
+
+/// Starts the timer that expires and prunes queued calls, drops rate counts
+/// and token spends older than a day, forgets expired approvals and drops
+/// the scheduled calls of expired sessions. Timers
+/// don't survive upgrades, so this runs from both `init` and `post_upgrade`.
+pub fn start_queue_sweep() {
+    ic_cdk_timers::set_timer_interval(QUEUE_SWEEP_INTERVAL, || {
+        WalletService::sweep_queue(api::time());
+        WalletService::prune_rate_counters(api::time());
+        WalletService::prune_token_spends(api::time());
+        WalletService::prune_approvals(api::time());
+        WalletService::prune_scheduled_calls();
+    });
+}
+
+/// Certifies what was stored before an upgrade to stable certification, a
+/// batch per round, see `certified::backfill`.
+pub fn start_certification_backfill() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if certified::backfill() {
+            start_certification_backfill();
+        }
+    });
+}
+
+/// Sets the one-shot timer that runs `run` when the earliest scheduled call is
+/// due, replacing the previous one. Call again whenever the schedule changes.
+pub fn arm_scheduler(run: impl FnOnce() + 'static) {
+    if let Some(timer) = SCHEDULER_TIMER.with(|t| t.take()) {
+        ic_cdk_timers::clear_timer(timer);
+    }
+    if let Some(at) = WalletService::next_scheduled_run() {
+        let delay = Duration::from_nanos(at.saturating_sub(api::time()));
+        let timer = ic_cdk_timers::set_timer(delay, run);
+        SCHEDULER_TIMER.with(|t| t.set(Some(timer)));
+    }
+}
+
+/// Runs a queued call approved for `user`, one-way if the method was
+/// registered as `OneWay` when the call was queued. Items that didn't record
+/// it go by the current session. The audit entry keeps the queue id. Token
+/// and NFT transfers count against the delegate's session like direct ones.
+pub async fn wallet_call_approved(
+    user: Principal,
+    args: CallCanisterArgs<u128>,
+    method_type: Option<MethodType>,
+    queue_id: &str,
+) -> Result<CallResult, String> {
+    let method_type = method_type
+        .or_else(|| WalletService::get_method_type(&user, &args.canister, &args.method_name));
+    token::record_transfer(&user, &args, api::time());
+    nft::record_transfers(&user, &args);
+    if method_type == Some(MethodType::OneWay) {
+        return notify_as(user, args.clone(), Some(queue_id))
+            .map(|_| CallResult { r#return: vec![] })
+            .map_err(|e| {
+                token::release_transfer(&user, &args);
+                nft::release_transfers(&user, &args, None);
+                describe_error(e)
+            });
+    }
+    let result = forward(user, args.clone(), Some(queue_id)).await.0;
+    let failed = match &result {
+        Ok(reply) => token::transfer_failed(&reply.r#return),
+        Err(_) => true,
+    };
+    if failed {
+        token::release_transfer(&user, &args);
+    }
+    let reply = result.as_ref().ok().map(|r| r.r#return.as_slice());
+    nft::release_transfers(&user, &args, reply);
+    result.map_err(describe_error)
+}
+
+pub(crate) fn describe_error((code, msg): (RejectionCode, String)) -> String {
+    format!("An error happened during the call: {}: {}", code as u8, msg)
+}
+
+/// Runs the calls of a batch approved for `user` in order and stops at the
+/// first one that fails, or whose arguments taken from an earlier reply break
+/// a policy. Returns the replies of the calls that ran and the
+/// batch outcome: the last reply, or the error that stopped it.
+pub async fn wallet_call_batch(
+    user: Principal,
+    calls: Vec<BatchCall>,
+    method_types: Option<Vec<MethodType>>,
+    queue_id: &str,
+) -> (Vec<CallResult>, Result<CallResult, String>) {
+    let mut replies: Vec<CallResult> = vec![];
+    for (index, call) in calls.iter().enumerate() {
+        let args = match call.resolve(&replies) {
+            Some(args) => args,
+            None => {
+                let error = format!("Call {} refers to a call that has not run", index);
+                return (replies, Err(error));
+            }
+        };
+        if call.args_from.is_some() {
+            if let Err(denial) = auth::check_resolved_args(&user, &args, api::time()) {
+                return (replies, Err(format!("Call {}: {}", index, denial)));
+            }
+        }
+        let method_type = method_types.as_ref().and_then(|t| t.get(index).cloned());
+        match wallet_call_approved(user, args, method_type, queue_id).await {
+            Ok(reply) => replies.push(reply),
+            Err(e) => return (replies, Err(format!("Call {}: {}", index, e))),
+        }
     }
+    let result = replies
+        .last()
+        .cloned()
+        .ok_or_else(|| "The batch is empty".to_string());
+    (replies, result)
+}
 
-    match api::call::call_raw128(args.canister, &args.method_name, &args.args, args.cycles).await {
-        Ok(x) => Ok(CallResult { r#return: x }),
-        Err((code, msg)) => Err(format!(
-            "An error happened during the call: {}: {}",
-            code as u8, msg
-        )),
+/// Sets every allowance the wallet is known to have granted back to zero, on
+/// behalf of `caller`. Allowances the ledger didn't revoke are kept.
+pub async fn revoke_approvals(caller: Principal) -> Vec<ApprovalRevocation> {
+    let mut revocations = vec![];
+    for approval in WalletService::list_approvals() {
+        let error = match forward(caller, token::revoke_call(&approval), None).await.0 {
+            Ok(reply) if token::transfer_failed(&reply.r#return) => Some(format!(
+                "The ledger refused: {}",
+                IDLArgs::from_bytes(&reply.r#return)
+                    .map_or_else(|e| e.to_string(), |a| a.to_string())
+            )),
+            Ok(_) => None,
+            Err(e) => Some(describe_error(e)),
+        };
+        revocations.push(ApprovalRevocation { approval, error });
     }
+    revocations
+}
+
+/// Sends a one-way call for `caller` and returns its audit entry id as the
+/// submission id. Only a reject from the system while sending is reported;
+/// the target's reply, if any, never comes back.
+pub fn notify_as(
+    caller: Principal,
+    args: CallCanisterArgs<u128>,
+    queue_id: Option<&str>,
+) -> Result<u64, (RejectionCode, String)> {
+    if api::id() == caller {
+        return Err((RejectionCode::CanisterReject, "Attempted to call forward on self. This is not allowed. Call this method via a different custodian.".to_string()));
+    }
+
+    match api::call::notify_raw(args.canister, &args.method_name, &args.args, args.cycles) {
+        Ok(()) => Ok(audit(caller, args, 0, AuditOutcome::Accepted, queue_id)),
+        Err(code) => {
+            let message = "The one-way call could not be sent".to_string();
+            let outcome = AuditOutcome::Rejected {
+                reject_code: code as u8,
+                message: message.clone(),
+            };
+            let cycles = args.cycles;
+            audit(caller, args, cycles, outcome, queue_id);
+            Err((code, message))
+        }
+    }
+}
+
+/// Forwards a call from a composite query. It carries no cycles and is not
+/// written to the audit log, since whatever a query changes is discarded.
+pub async fn query_call(
+    args: CallCanisterArgs<u128>,
+) -> Result<CallResult, (RejectionCode, String)> {
+    api::call::call_raw(args.canister, &args.method_name, &args.args, 0)
+        .await
+        .map(|x| CallResult { r#return: x })
+}
+
+/// Forwards a call made on behalf of `caller` and reports how many of the
+/// attached cycles came back: the callee's refund on reply, all of them on reject.
+pub async fn wallet_call_with_refund(
+    caller: Principal,
+    args: CallCanisterArgs<u128>,
+) -> (Result<CallResult, (RejectionCode, String)>, u128) {
+    forward(caller, args, None).await
+}
+
+/// Every forwarded call goes through here and is written to the audit log.
+async fn forward(
+    caller: Principal,
+    args: CallCanisterArgs<u128>,
+    queue_id: Option<&str>,
+) -> (Result<CallResult, (RejectionCode, String)>, u128) {
+    if api::id() == caller {
+        return (Err((RejectionCode::CanisterReject, "Attempted to call forward on self. This is not allowed. Call this method via a different custodian.".to_string())), 0);
+    }
+
+    let result = api::call::call_raw128(args.canister, &args.method_name, &args.args, args.cycles)
+        .await
+        .map(|x| CallResult { r#return: x });
+    let refunded = match result {
+        Ok(_) => api::call::msg_cycles_refunded128(),
+        Err(_) => args.cycles,
+    };
+    if let Ok(reply) = &result {
+        token::track_approval(&caller, &args, &reply.r#return, api::time());
+    }
+    let outcome = match &result {
+        Ok(_) => AuditOutcome::Replied,
+        Err((code, message)) => AuditOutcome::Rejected {
+            reject_code: *code as u8,
+            message: message.clone(),
+        },
+    };
+    audit(caller, args, refunded, outcome, queue_id);
+    (result, refunded)
+}
+
+fn audit(
+    caller: Principal,
+    args: CallCanisterArgs<u128>,
+    refunded: u128,
+    outcome: AuditOutcome,
+    queue_id: Option<&str>,
+) -> u64 {
+    WalletService::add_audit_entry(AuditEntry {
+        id: 0,
+        caller,
+        canister: args.canister,
+        method_name: args.method_name,
+        arg_hash: WalletService::audit_arg_hash(&args.args),
+        cycles: args.cycles,
+        cycles_refunded: refunded,
+        outcome,
+        time: api::time(),
+        queue_id: queue_id.map(str::to_string),
+    })
 }
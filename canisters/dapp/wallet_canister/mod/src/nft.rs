@@ -0,0 +1,190 @@
+use crate::service::WalletService;
+use crate::token::{account_identifier, parse_account_identifier, AccountIdentifier};
+use crate::types::{Account, CallCanisterArgs, Denial, ExpiryUser, NftPolicy};
+use candid::parser::value::IDLValue;
+use candid::{CandidType, Decode, IDLArgs, Nat};
+use ic_cdk::export::Principal;
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+
+pub const ICRC7_TRANSFER: &str = "icrc7_transfer";
+/// EXT collections name their transfer method like the legacy ICP ledger.
+pub const EXT_TRANSFER: &str = "transfer";
+
+/// One transfer of an `icrc7_transfer` call, which takes a batch of them.
+#[derive(CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<ByteBuf>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<ByteBuf>,
+    pub created_at_time: Option<u64>,
+}
+
+/// An EXT account: an account identifier in hex, or a principal's default account.
+#[derive(CandidType, Deserialize)]
+pub enum User {
+    #[serde(rename = "address")]
+    Address(String),
+    #[serde(rename = "principal")]
+    Principal(Principal),
+}
+
+/// Argument of an EXT collection's `transfer`.
+#[derive(CandidType, Deserialize)]
+pub struct TransferRequest {
+    pub from: User,
+    pub to: User,
+    pub token: String,
+    pub notify: bool,
+    pub memo: ByteBuf,
+    pub subaccount: Option<ByteBuf>,
+    pub amount: Nat,
+}
+
+/// A token a call moves out of the wallet. Recipients are compared by
+/// account identifier, as EXT addresses them.
+pub struct NftTransfer {
+    pub token_id: String,
+    pub to: AccountIdentifier,
+}
+
+/// The policy the delegate has for `collection`, if any.
+pub fn nft_policy<'a>(session: &'a ExpiryUser, collection: &Principal) -> Option<&'a NftPolicy> {
+    session
+        .target_list
+        .iter()
+        .filter(|d| d.canister.eq(collection))
+        .find_map(|d| d.nft_policy.as_ref())
+}
+
+/// Decodes the transfers `args` makes, or `None` when the call moves no tokens.
+pub fn decode_transfers(args: &CallCanisterArgs<u128>) -> Result<Option<Vec<NftTransfer>>, String> {
+    match args.method_name.as_str() {
+        ICRC7_TRANSFER => {
+            let batch = Decode!(&args.args, Vec<TransferArg>)
+                .map_err(|e| format!("Invalid transfer argument: {}", e))?;
+            Ok(Some(
+                batch
+                    .into_iter()
+                    .map(|arg| NftTransfer {
+                        token_id: arg.token_id.0.to_string(),
+                        to: account_identifier(&arg.to),
+                    })
+                    .collect(),
+            ))
+        }
+        EXT_TRANSFER => {
+            let arg = Decode!(&args.args, TransferRequest)
+                .map_err(|e| format!("Invalid transfer argument: {}", e))?;
+            let to = match arg.to {
+                User::Address(address) => {
+                    let bytes = hex::decode(&address)
+                        .map_err(|e| format!("Invalid account identifier {}: {}", address, e))?;
+                    parse_account_identifier(&bytes)?
+                }
+                User::Principal(owner) => account_identifier(&Account {
+                    owner,
+                    subaccount: None,
+                }),
+            };
+            Ok(Some(vec![NftTransfer {
+                token_id: arg.token,
+                to,
+            }]))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Holds the transfers of a call to the policy of its collection. Returns
+/// whether the policy wants the call approved by the owner.
+pub fn check_nft_call(session: &ExpiryUser, args: &CallCanisterArgs<u128>) -> Result<bool, Denial> {
+    let policy = match nft_policy(session, &args.canister) {
+        None => return Ok(false),
+        Some(policy) => policy,
+    };
+    let rejected = |reason: String| Denial::TransferRejected { reason };
+    let transfers = match decode_transfers(args).map_err(rejected)? {
+        None => return Ok(false),
+        Some(transfers) => transfers,
+    };
+    for transfer in &transfers {
+        if let Some(token_ids) = &policy.token_ids {
+            if !token_ids.contains(&transfer.token_id) {
+                return Err(rejected(format!(
+                    "Token {} is not allowed",
+                    transfer.token_id
+                )));
+            }
+        }
+        if let Some(recipients) = &policy.recipients {
+            if !recipients
+                .iter()
+                .any(|r| account_identifier(r) == transfer.to)
+            {
+                return Err(rejected(format!(
+                    "Recipient {} is not allowed",
+                    hex::encode(transfer.to)
+                )));
+            }
+        }
+    }
+    match policy.max_transfers {
+        Some(left) if transfers.len() as u128 > left as u128 => Err(Denial::AllowanceExceeded {
+            requested: transfers.len() as u128,
+            remaining: left as u128,
+        }),
+        _ => Ok(policy.requires_approval),
+    }
+}
+
+/// Counts the transfers of a call the caller was let through against their session.
+pub fn record_transfers(caller: &Principal, args: &CallCanisterArgs<u128>) {
+    if let Some(count) = transfer_count(caller, args) {
+        WalletService::reserve_nft_transfers(caller, &args.canister, count);
+    }
+}
+
+/// Gives back the transfers the collection did not make: all of them when
+/// the call was rejected, otherwise those its `reply` reports as failed.
+pub fn release_transfers(caller: &Principal, args: &CallCanisterArgs<u128>, reply: Option<&[u8]>) {
+    let count = match (transfer_count(caller, args), reply) {
+        (None, _) => return,
+        (Some(count), None) => count,
+        (Some(count), Some(reply)) => failed_transfers(reply).min(count),
+    };
+    if count > 0 {
+        WalletService::refund_nft_transfers(caller, &args.canister, count);
+    }
+}
+
+fn transfer_count(caller: &Principal, args: &CallCanisterArgs<u128>) -> Option<u32> {
+    let session = WalletService::get_expiry_user(caller)?;
+    nft_policy(&session, &args.canister)?;
+    Some(decode_transfers(args).ok()??.len() as u32)
+}
+
+/// Transfers a collection replied it did not make. ICRC-7 replies with an
+/// optional result per transfer, where a missing one was never processed,
+/// EXT with a single `ok` or `err`.
+fn failed_transfers(reply: &[u8]) -> u32 {
+    match IDLArgs::from_bytes(reply).map(|a| a.args) {
+        Ok(values) => match values.first() {
+            Some(IDLValue::Vec(results)) => results
+                .iter()
+                .filter(|r| match r {
+                    IDLValue::Opt(result) => is_variant(result, "Err"),
+                    _ => true,
+                })
+                .count() as u32,
+            Some(value) => is_variant(value, "err") as u32,
+            None => 0,
+        },
+        Err(_) => 0,
+    }
+}
+
+fn is_variant(value: &IDLValue, name: &str) -> bool {
+    matches!(value, IDLValue::Variant(v) if v.0.id.get_id() == candid::idl_hash(name))
+}
@@ -0,0 +1,125 @@
+use crate::types::{ArgConstraint, ArgPolicy, ArgRule};
+use candid::parser::types::IDLTypes;
+use candid::parser::value::IDLValue;
+use candid::types::Label;
+use candid::{IDLArgs, Int, TypeEnv};
+
+/// Decodes `args` and checks every constraint of `policy` against it.
+pub fn check_args(policy: &ArgPolicy, args: &[u8]) -> Result<(), String> {
+    if policy.signature.is_none() && policy.constraints.is_empty() {
+        return Ok(());
+    }
+    let values = decode_args(policy.signature.as_deref(), args)?;
+    for constraint in policy.constraints.iter() {
+        check_constraint(constraint, &values)?;
+    }
+    Ok(())
+}
+
+pub fn decode_args(signature: Option<&str>, args: &[u8]) -> Result<Vec<IDLValue>, String> {
+    let decoded = match signature {
+        None => IDLArgs::from_bytes(args),
+        Some(signature) => {
+            let types = signature
+                .parse::<IDLTypes>()
+                .map_err(|e| format!("Invalid argument signature {}: {}", signature, e))?;
+            let env = TypeEnv::new();
+            let types = types
+                .args
+                .iter()
+                .map(|t| env.ast_to_type(t))
+                .collect::<candid::Result<Vec<_>>>()
+                .map_err(|e| format!("Invalid argument signature {}: {}", signature, e))?;
+            IDLArgs::from_bytes_with_types(args, &env, &types)
+        }
+    };
+    decoded
+        .map(|a| a.args)
+        .map_err(|e| format!("Arguments do not match the method signature: {}", e))
+}
+
+fn check_constraint(constraint: &ArgConstraint, values: &[IDLValue]) -> Result<(), String> {
+    let value = values
+        .get(constraint.arg as usize)
+        .and_then(|v| lookup(v, &constraint.path))
+        .ok_or_else(|| format!("Argument {} is missing", describe(constraint)))?;
+    if check_rule(&constraint.rule, value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Argument {} violates constraint {}",
+            describe(constraint),
+            describe_rule(&constraint.rule)
+        ))
+    }
+}
+
+/// Walks record and variant fields named by `path`, looking through `opt` values.
+fn lookup<'a>(value: &'a IDLValue, path: &[String]) -> Option<&'a IDLValue> {
+    let value = match value {
+        IDLValue::Opt(inner) => return lookup(inner, path),
+        v => v,
+    };
+    let (name, rest) = match path.split_first() {
+        None => return Some(value),
+        Some(r) => r,
+    };
+    let label = match name.parse::<u32>() {
+        Ok(id) => Label::Id(id),
+        Err(_) => Label::Named(name.clone()),
+    };
+    match value {
+        IDLValue::Record(fields) => fields
+            .iter()
+            .find(|f| f.id == label)
+            .and_then(|f| lookup(&f.val, rest)),
+        IDLValue::Variant(variant) if variant.0.id == label => lookup(&variant.0.val, rest),
+        _ => None,
+    }
+}
+
+fn check_rule(rule: &ArgRule, value: &IDLValue) -> bool {
+    match rule {
+        ArgRule::PrincipalEq(expected) => {
+            matches!(value, IDLValue::Principal(p) if p == expected)
+        }
+        ArgRule::PrincipalIn(allowed) => {
+            matches!(value, IDLValue::Principal(p) if allowed.contains(p))
+        }
+        ArgRule::TextIn(allowed) => {
+            matches!(value, IDLValue::Text(t) if allowed.contains(t))
+        }
+        ArgRule::NatMax(max) => to_int(value).is_some_and(|v| v <= Int::from(max.clone())),
+    }
+}
+
+fn to_int(value: &IDLValue) -> Option<Int> {
+    match value {
+        IDLValue::Nat(v) => Some(Int::from(v.clone())),
+        IDLValue::Nat8(v) => Some(Int::from(*v)),
+        IDLValue::Nat16(v) => Some(Int::from(*v)),
+        IDLValue::Nat32(v) => Some(Int::from(*v)),
+        IDLValue::Nat64(v) => Some(Int::from(*v)),
+        IDLValue::Int(v) => Some(v.clone()),
+        IDLValue::Int8(v) => Some(Int::from(*v)),
+        IDLValue::Int16(v) => Some(Int::from(*v)),
+        IDLValue::Int32(v) => Some(Int::from(*v)),
+        IDLValue::Int64(v) => Some(Int::from(*v)),
+        _ => None,
+    }
+}
+
+fn describe(constraint: &ArgConstraint) -> String {
+    let mut path = vec![constraint.arg.to_string()];
+    path.extend(constraint.path.iter().cloned());
+    path.join(".")
+}
+
+fn describe_rule(rule: &ArgRule) -> String {
+    match rule {
+        ArgRule::PrincipalEq(p) => format!("== {}", p),
+        ArgRule::PrincipalIn(list) => format!("in {} allowed principals", list.len()),
+        ArgRule::TextIn(list) => format!("in {} allowed texts", list.len()),
+        ArgRule::NatMax(max) => format!("<= {}", max),
+    }
+}
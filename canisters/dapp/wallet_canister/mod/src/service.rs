@@ -1,36 +1,193 @@
+use crate::certified;
+use crate::memory::{self, Memory};
+use crate::policy;
 use crate::types::{
-    ExpiryUser, MethodQueueItem, MethodType, MethodValidationType, OwnerReply, ProxyActorTargets,
-    QueueHash, Settings, WalletStore,
+    Approval, AuditEntry, AuditFilter, AuditOutcome, AuditPage, BatchCall, CallResult,
+    CertificationBackfill, Denial, ExpiryUser, Method, MethodQueueItem, MethodType,
+    MethodValidationType, OwnerReply, PrincipalKey, ProxyActorTargets, ProxyCallResult,
+    QueueFilter, QueueHash, QueueItemView, QueuePage, QueueStatus, RateCounter, RateKey, RateLimit,
+    ScheduledCall, Settings, SettingsV2, SettingsV3, StableWalletStore, TokenSpend, Vote,
+    WalletStore, WalletStoreV2, WalletStoreV3, WalletStoreV4, WalletStoreV5, WalletStoreV6,
+    WalletStoreV7, WalletStoreV8, WalletStoreV9, DAY, HOUR, MINUTE,
 };
 use crate::CallCanisterArgs;
+use candid::IDLArgs;
 use ic_cdk::api;
 use ic_cdk::export::Principal;
+use ic_stable_structures::{StableBTreeMap, StableLog};
 use itertools::Itertools;
 use sha2::{Digest, Sha256};
 
 use std::cell::RefCell;
+use std::ops::Bound;
 
 thread_local! {
-    pub static WALLET_STORE: RefCell<WalletStore<u128>> = RefCell::new(WalletStore::default());
+    pub static WALLET_STORE: RefCell<WalletStore> = RefCell::new(WalletStore::default());
+
+    pub static EXPIRY_USERS: RefCell<StableBTreeMap<PrincipalKey, ExpiryUser, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get_expiry_users_memory()));
+
+    pub static CALL_QUEUE: RefCell<StableBTreeMap<String, MethodQueueItem<u128>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get_call_queue_memory()));
+
+    pub static AUDIT_LOG: RefCell<StableLog<AuditEntry, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            memory::get_audit_log_index_memory(),
+            memory::get_audit_log_data_memory(),
+        )
+        .expect("failed to init the audit log"),
+    );
 }
-pub fn pre_upgrade() -> WalletStore<u128> {
-    WALLET_STORE.with(|s| s.take().into())
+
+pub fn pre_upgrade() -> StableWalletStore {
+    WALLET_STORE.with(|s| StableWalletStore::V10(s.take()))
 }
 
-pub fn post_upgrade(stable_state: WalletStore<u128>) {
-    WALLET_STORE.with(|s| s.replace(stable_state));
+pub fn post_upgrade(stable_state: StableWalletStore) {
+    let store = migrate(stable_state);
+    WALLET_STORE.with(|s| s.replace(store));
 }
 
-impl Default for WalletStore<u128> {
+/// Brings a store saved by any previous schema version up to the current layout.
+pub fn migrate(stable_state: StableWalletStore) -> WalletStore {
+    match stable_state {
+        StableWalletStore::V1(store) => {
+            EXPIRY_USERS.with(|m| {
+                let mut map = m.borrow_mut();
+                for (user, expiry_user) in store.expiry_users {
+                    map.insert(PrincipalKey(user), expiry_user);
+                }
+            });
+            CALL_QUEUE.with(|m| {
+                let mut map = m.borrow_mut();
+                for (hash, item) in store.call_queue {
+                    map.insert(hash, item);
+                }
+            });
+            migrate(StableWalletStore::V2(WalletStoreV2 {
+                settings: store.settings,
+            }))
+        }
+        StableWalletStore::V2(store) => migrate(StableWalletStore::V3(WalletStoreV3 {
+            settings: store.settings,
+            queue_nonce: 0,
+        })),
+        // calls queued so far keep `expires_at: None`, see `MethodQueueItem::deadline`
+        StableWalletStore::V3(store) => migrate(StableWalletStore::V4(WalletStoreV4 {
+            settings: SettingsV2 {
+                expiry_period: store.settings.expiry_period,
+                proxy_black_list: store.settings.proxy_black_list,
+                method_valid_type: store.settings.method_valid_type,
+                queue_approval_period: QUEUE_APPROVAL_PERIOD,
+                queue_retention_period: QUEUE_RETENTION_PERIOD,
+            },
+            queue_nonce: store.queue_nonce,
+        })),
+        StableWalletStore::V4(store) => migrate(StableWalletStore::V5(WalletStoreV5 {
+            settings: SettingsV3 {
+                expiry_period: store.settings.expiry_period,
+                proxy_black_list: store.settings.proxy_black_list,
+                method_valid_type: store.settings.method_valid_type,
+                queue_approval_period: store.settings.queue_approval_period,
+                queue_retention_period: store.settings.queue_retention_period,
+                approvers: Default::default(),
+                approval_threshold: 1,
+                key_operation_threshold: None,
+            },
+            queue_nonce: store.queue_nonce,
+        })),
+        StableWalletStore::V5(store) => migrate(StableWalletStore::V6(WalletStoreV6 {
+            settings: store.settings,
+            queue_nonce: store.queue_nonce,
+            scheduled_calls: Default::default(),
+            schedule_nonce: 0,
+        })),
+        StableWalletStore::V6(store) => migrate(StableWalletStore::V7(WalletStoreV7 {
+            settings: Settings {
+                expiry_period: store.settings.expiry_period,
+                proxy_black_list: store.settings.proxy_black_list,
+                method_valid_type: store.settings.method_valid_type,
+                queue_approval_period: store.settings.queue_approval_period,
+                queue_retention_period: store.settings.queue_retention_period,
+                approvers: store.settings.approvers,
+                approval_threshold: store.settings.approval_threshold,
+                key_operation_threshold: store.settings.key_operation_threshold,
+                rate_limit: None,
+            },
+            queue_nonce: store.queue_nonce,
+            scheduled_calls: store.scheduled_calls,
+            schedule_nonce: store.schedule_nonce,
+            rate_counters: Default::default(),
+        })),
+        StableWalletStore::V7(store) => migrate(StableWalletStore::V8(WalletStoreV8 {
+            settings: store.settings,
+            queue_nonce: store.queue_nonce,
+            scheduled_calls: store.scheduled_calls,
+            schedule_nonce: store.schedule_nonce,
+            rate_counters: store.rate_counters,
+            token_spends: Default::default(),
+        })),
+        StableWalletStore::V8(store) => migrate(StableWalletStore::V9(WalletStoreV9 {
+            settings: store.settings,
+            queue_nonce: store.queue_nonce,
+            scheduled_calls: store.scheduled_calls,
+            schedule_nonce: store.schedule_nonce,
+            rate_counters: store.rate_counters,
+            token_spends: store.token_spends,
+            approvals: vec![],
+        })),
+        // the certification tree was rebuilt on the heap by every upgrade,
+        // what is already stored gets certified in batches from now on
+        StableWalletStore::V9(store) => WalletStore {
+            settings: store.settings,
+            queue_nonce: store.queue_nonce,
+            scheduled_calls: store.scheduled_calls,
+            schedule_nonce: store.schedule_nonce,
+            rate_counters: store.rate_counters,
+            token_spends: store.token_spends,
+            approvals: store.approvals,
+            certification_backfill: Some(CertificationBackfill::default()),
+            sweep_cursor: None,
+        },
+        StableWalletStore::V10(store) => store,
+    }
+}
+
+/// Queued calls or audit entries one page of `list_queue` or
+/// `list_audit_log` looks at, matching or not.
+const LIST_SCAN_LIMIT: usize = 1000;
+
+/// Queued calls one `sweep_queue` looks at.
+const QUEUE_SWEEP_BATCH: usize = 500;
+
+/// How long a call may be `Executing` before `sweep_queue` takes it as failed.
+const QUEUE_EXECUTION_TIMEOUT: u64 = HOUR;
+
+pub(crate) const QUEUE_APPROVAL_PERIOD: u64 = 24 * 60 * 60 * 1000 * 1000 * 1000;
+const QUEUE_RETENTION_PERIOD: u64 = 7 * 24 * 60 * 60 * 1000 * 1000 * 1000;
+
+impl Default for WalletStore {
     fn default() -> Self {
         WalletStore {
-            expiry_users: Default::default(),
             settings: Settings {
                 expiry_period: 7 * 24 * 60 * 60 * 1000 * 1000 * 1000,
                 proxy_black_list: Default::default(),
                 method_valid_type: MethodValidationType::KEY,
+                queue_approval_period: QUEUE_APPROVAL_PERIOD,
+                queue_retention_period: QUEUE_RETENTION_PERIOD,
+                approvers: Default::default(),
+                approval_threshold: 1,
+                key_operation_threshold: None,
+                rate_limit: None,
             },
-            call_queue: Default::default(),
+            queue_nonce: 0,
+            scheduled_calls: Default::default(),
+            schedule_nonce: 0,
+            rate_counters: Default::default(),
+            token_spends: Default::default(),
+            approvals: vec![],
+            certification_backfill: None,
+            sweep_cursor: None,
         }
     }
 }
@@ -42,125 +199,420 @@ impl WalletService {
         WalletService::remove_all_expiries();
         let actual_period = targets
             .expiration
-            .map_or_else(|| WalletService::get_setting().expiry_period, |v| v);
+            .unwrap_or_else(|| WalletService::get_setting().expiry_period);
 
         let ts = api::time();
         let rt = ExpiryUser {
-            user: user.clone(),
+            user,
             target_list: targets.targets,
-            timestamp: ts.clone(),
-            expiry_timestamp: actual_period + ts,
+            timestamp: ts,
+            expiry_timestamp: ts.saturating_add(actual_period),
+            cycles: targets.cycles,
+            rate_limit: targets.rate_limit,
         };
-        WALLET_STORE.with(|s| {
-            let mut store = s.borrow_mut();
-            store.expiry_users.insert(user.clone(), rt.clone());
+        EXPIRY_USERS.with(|m| {
+            m.borrow_mut().insert(PrincipalKey(user), rt.clone());
             rt.clone()
         })
     }
 
-    pub fn is_valid_canister(user: &Principal, canister: &Principal) -> bool {
-        match WalletService::get_expiry_user(user) {
-            None => false,
-            Some(r) => r.target_list.iter().any(|d| d.canister.eq(canister)),
+    pub fn get_method_type(
+        user: &Principal,
+        canister: &Principal,
+        method_name: &str,
+    ) -> Option<MethodType> {
+        WalletService::get_method(user, canister, method_name).map(|m| m.method_type)
+    }
+
+    pub fn get_method(user: &Principal, canister: &Principal, method_name: &str) -> Option<Method> {
+        WalletService::get_expiry_user(user)?
+            .method(canister, method_name)
+            .cloned()
+    }
+
+    /// Takes `args.cycles` out of the delegate's session budget and, if the
+    /// method has one, its method budget. Returns false and leaves both
+    /// untouched when either of them can't cover the call.
+    pub fn reserve_cycles(user: &Principal, args: &CallCanisterArgs<u128>) -> bool {
+        if args.cycles == 0 {
+            return true;
         }
+        let mut expiry_user = match WalletService::get_expiry_user(user) {
+            None => return false,
+            Some(r) => r,
+        };
+        match expiry_user.cycles.as_mut() {
+            Some(budget) if budget.allows(args.cycles) => budget.total -= args.cycles,
+            _ => return false,
+        }
+        let method_budget = expiry_user
+            .method_mut(&args.canister, &args.method_name)
+            .and_then(|m| m.cycles.as_mut());
+        if let Some(budget) = method_budget {
+            if !budget.allows(args.cycles) {
+                return false;
+            }
+            budget.total -= args.cycles;
+        }
+        EXPIRY_USERS.with(|m| m.borrow_mut().insert(PrincipalKey(*user), expiry_user));
+        true
     }
 
-    pub fn is_valid_canister_method(
-        user: &Principal,
-        canister: &Principal,
-        method_name: &String,
-    ) -> bool {
-        match WalletService::get_expiry_user(user) {
-            None => false,
-            Some(r) => r
-                .target_list
-                .iter()
-                .any(|d| d.canister.eq(canister) && d.methods.contains_key(method_name)),
+    /// Gives cycles the callee did not keep back to the budgets charged by `reserve_cycles`.
+    pub fn refund_cycles(user: &Principal, canister: &Principal, method_name: &str, cycles: u128) {
+        if cycles == 0 {
+            return;
+        }
+        if let Some(mut expiry_user) = WalletService::get_expiry_user(user) {
+            if let Some(budget) = expiry_user.cycles.as_mut() {
+                budget.total = budget.total.saturating_add(cycles);
+            }
+            let method_budget = expiry_user
+                .method_mut(canister, method_name)
+                .and_then(|m| m.cycles.as_mut());
+            if let Some(budget) = method_budget {
+                budget.total = budget.total.saturating_add(cycles);
+            }
+            EXPIRY_USERS.with(|m| m.borrow_mut().insert(PrincipalKey(*user), expiry_user));
         }
     }
 
-    pub fn get_method_type(
-        user: &Principal,
-        canister: &Principal,
-        method_name: &String,
-    ) -> Option<MethodType> {
-        match WalletService::get_expiry_user(user) {
-            None => None,
-            Some(r) => r
+    /// Takes `count` transfers from what the delegate's `NftPolicy` has left on `collection`.
+    pub fn reserve_nft_transfers(user: &Principal, collection: &Principal, count: u32) {
+        WalletService::update_nft_transfers(user, collection, |left| left.saturating_sub(count));
+    }
+
+    /// Gives back transfers charged by `reserve_nft_transfers` that the collection did not make.
+    pub fn refund_nft_transfers(user: &Principal, collection: &Principal, count: u32) {
+        WalletService::update_nft_transfers(user, collection, |left| left.saturating_add(count));
+    }
+
+    fn update_nft_transfers(user: &Principal, collection: &Principal, f: impl Fn(u32) -> u32) {
+        if let Some(mut expiry_user) = WalletService::get_expiry_user(user) {
+            let left = expiry_user
                 .target_list
-                .iter()
-                .find(|d| d.canister.eq(canister) && d.methods.get(method_name.as_str()).is_some())
-                .map_or_else(
-                    || None,
-                    |e| {
-                        e.methods
-                            .get(method_name.as_str())
-                            .map_or_else(|| None, |re| Some(re.method_type.clone()))
-                    },
-                ),
+                .iter_mut()
+                .filter(|d| d.canister.eq(collection))
+                .find_map(|d| d.nft_policy.as_mut())
+                .and_then(|p| p.max_transfers.as_mut());
+            if let Some(left) = left {
+                *left = f(*left);
+                EXPIRY_USERS.with(|m| m.borrow_mut().insert(PrincipalKey(*user), expiry_user));
+            }
         }
     }
 
-    pub fn is_method_key_oper(
+    /// Builds the queue entry for a call. It can be approved for the configured
+    /// approval period, but not past the end of the caller's session, and needs
+    /// the method's threshold, or the key operation threshold for key operations.
+    pub fn hash_method(user: &Principal, args: CallCanisterArgs<u128>) -> MethodQueueItem<u128> {
+        WalletService::new_queue_item(user, args, None)
+    }
+
+    /// Builds one queue entry for a whole batch. It needs the highest
+    /// threshold of its calls.
+    pub fn hash_batch(user: &Principal, calls: Vec<BatchCall>) -> MethodQueueItem<u128> {
+        WalletService::new_queue_item(user, calls[0].call.clone(), Some(calls))
+    }
+
+    fn new_queue_item(
         user: &Principal,
-        canister: &Principal,
-        method_name: &String,
-    ) -> bool {
-        match WalletService::get_expiry_user(user) {
-            None => false,
-            Some(r) => r
-                .target_list
+        payload: CallCanisterArgs<u128>,
+        batch: Option<Vec<BatchCall>>,
+    ) -> MethodQueueItem<u128> {
+        let ts = api::time();
+        let nonce = WalletService::next_queue_nonce();
+        let settings = WalletService::get_setting();
+        let deadline = ts.saturating_add(settings.queue_approval_period);
+        let threshold = match &batch {
+            None => WalletService::method_threshold(user, &payload, &settings),
+            Some(calls) => calls
                 .iter()
-                .find(|d| {
-                    d.canister.eq(canister)
-                        && d.methods
-                            .get(method_name.as_str())
-                            .map_or_else(|| false, |e| e.key_operation)
-                })
-                .is_some(),
+                .filter_map(|c| WalletService::method_threshold(user, &c.call, &settings))
+                .max(),
+        };
+        let expires_at = match WalletService::get_expiry_user(user) {
+            None => deadline,
+            Some(r) => deadline.min(r.expiry_timestamp),
+        };
+        let method_types = match &batch {
+            None => vec![&payload],
+            Some(calls) => calls.iter().map(|c| &c.call).collect(),
         }
+        .into_iter()
+        .map(|args| {
+            WalletService::get_method_type(user, &args.canister, &args.method_name)
+                .unwrap_or(MethodType::CALL)
+        })
+        .collect();
+        let mut item = MethodQueueItem {
+            hash: String::new(),
+            user: *user,
+            time_stamp: ts,
+            nonce: Some(nonce),
+            payload,
+            owner_reply: OwnerReply::NotFound,
+            status: Some(QueueStatus::Pending),
+            expires_at: Some(expires_at),
+            updated_at: Some(ts),
+            threshold,
+            votes: Some(vec![]),
+            batch,
+            batch_results: None,
+            method_types: Some(method_types),
+        };
+        item.hash = WalletService::queue_hash(&item, nonce);
+        item
     }
 
-    pub fn hash_method(user: &Principal, args: CallCanisterArgs<u128>) -> MethodQueueItem<u128> {
+    fn method_threshold(
+        user: &Principal,
+        args: &CallCanisterArgs<u128>,
+        settings: &Settings,
+    ) -> Option<u32> {
+        WalletService::get_method(user, &args.canister, &args.method_name).and_then(|m| {
+            m.approval_threshold.or(if m.key_operation {
+                settings.key_operation_threshold
+            } else {
+                None
+            })
+        })
+    }
+
+    /// Queue id over the full payload: caller, canister, method, arguments,
+    /// cycles and argument references of every call, queueing time and nonce.
+    /// Every variable-length field and the list of calls is length-prefixed,
+    /// and whether the item is a batch and whether a call has an argument
+    /// reference is tagged, so different payloads can't produce the same byte
+    /// stream.
+    pub fn queue_hash(item: &MethodQueueItem<u128>, nonce: u64) -> String {
+        let calls = match &item.batch {
+            None => vec![(&item.payload, None)],
+            Some(calls) => calls.iter().map(|c| (&c.call, c.args_from)).collect(),
+        };
         let mut sha = Sha256::default();
-        let ts = ic_cdk::api::time();
-        sha.update(user.clone().as_slice());
-        sha.update(args.canister.as_slice());
-        sha.update(args.method_name.as_bytes());
-        sha.update(ts.to_be_bytes().as_slice());
-
-        MethodQueueItem {
-            hash: hex::encode(sha.finalize().as_slice()),
-            user: user.clone(),
-            time_stamp: ts.clone(),
-            payload: args,
-            owner_reply: OwnerReply::NotFound,
+        sha.update((item.user.as_slice().len() as u64).to_be_bytes());
+        sha.update(item.user.as_slice());
+        sha.update([item.batch.is_some() as u8]);
+        sha.update((calls.len() as u64).to_be_bytes());
+        for (args, args_from) in calls {
+            for field in [
+                args.canister.as_slice(),
+                args.method_name.as_bytes(),
+                args.args.as_slice(),
+            ] {
+                sha.update((field.len() as u64).to_be_bytes());
+                sha.update(field);
+            }
+            sha.update(args.cycles.to_be_bytes());
+            match args_from {
+                None => sha.update([0]),
+                Some(index) => {
+                    sha.update([1]);
+                    sha.update(index.to_be_bytes());
+                }
+            }
         }
+        sha.update(item.time_stamp.to_be_bytes());
+        sha.update(nonce.to_be_bytes());
+        hex::encode(sha.finalize().as_slice())
     }
 
-    pub fn update_queue_reply(hash: String, reply: OwnerReply) -> Option<OwnerReply> {
+    fn next_queue_nonce() -> u64 {
         WALLET_STORE.with(|s| {
             let mut store = s.borrow_mut();
-            store.call_queue.get_mut(&hash).map_or_else(
-                || None,
-                |r| {
-                    r.owner_reply = reply.clone();
-                    Some(reply.clone())
-                },
-            )
+            store.queue_nonce += 1;
+            store.queue_nonce
         })
     }
 
-    pub fn get_queue_method(hash: String) -> Option<MethodQueueItem<u128>> {
-        WALLET_STORE.with(|s| {
-            let store = s.borrow();
-            match store.call_queue.get(&hash) {
-                None => None,
-                Some(r) => Some(r.clone()),
+    /// Records `approver`'s vote on a pending call, replacing an earlier vote of
+    /// theirs. The call moves to `Executing` once it has enough approvals and to
+    /// `Rejected` once it has enough vetoes, see `Settings::approval_rule`.
+    /// Only votes of the current approvers are counted. Fails with the call's
+    /// current status when it is not pending, or `None` when there is no such
+    /// call. A pending call that lapsed, see `is_lapsed`, is expired instead.
+    pub fn vote_queue_method(
+        hash: &str,
+        approver: Principal,
+        approve: bool,
+    ) -> Result<MethodQueueItem<u128>, Option<QueueStatus>> {
+        let now = api::time();
+        let settings = WalletService::get_setting();
+        CALL_QUEUE.with(|m| {
+            let mut queue = m.borrow_mut();
+            let mut item = WalletService::get_live_queue_method(&mut queue, hash, now)?;
+            let status = item.status();
+            if status != QueueStatus::Pending {
+                return Err(Some(status));
             }
+            let votes = item.votes.get_or_insert_with(Vec::new);
+            votes.retain(|v| v.approver != approver);
+            votes.push(Vote {
+                approver,
+                approve,
+                time: now,
+            });
+            let (approvals, vetoes) = settings.count_votes(votes);
+            let (required, veto) = settings.approval_rule(item.threshold);
+            if approvals >= required {
+                item.status = Some(QueueStatus::Executing);
+            } else if vetoes >= veto {
+                item.status = Some(QueueStatus::Rejected);
+                item.owner_reply = OwnerReply::Rejected(hash.to_string());
+            }
+            item.updated_at = Some(now);
+            certified::certify_queue_item(&item);
+            queue.insert(hash.to_string(), item.clone());
+            Ok(item)
         })
     }
 
+    /// The reply to a vote that left `item` pending: the approvals counted so
+    /// far and the number it needs to run.
+    pub fn pending_reply(item: &MethodQueueItem<u128>) -> OwnerReply {
+        let settings = WalletService::get_setting();
+        let (approvals, _) = settings.count_votes(item.votes.as_deref().unwrap_or_default());
+        let (threshold, _) = settings.approval_rule(item.threshold);
+        OwnerReply::Pending {
+            approvals,
+            threshold,
+        }
+    }
+
+    /// Looks up a queued call, expiring it first if it is pending but lapsed.
+    fn get_live_queue_method(
+        queue: &mut StableBTreeMap<String, MethodQueueItem<u128>, Memory>,
+        hash: &str,
+        now: u64,
+    ) -> Result<MethodQueueItem<u128>, Option<QueueStatus>> {
+        let mut item = queue.get(&hash.to_string()).ok_or(None)?;
+        if item.status() == QueueStatus::Pending && WalletService::is_lapsed(&item, now) {
+            item.status = Some(QueueStatus::Expired);
+            item.updated_at = Some(now);
+            certified::certify_queue_item(&item);
+            queue.insert(hash.to_string(), item.clone());
+        }
+        Ok(item)
+    }
+
+    /// Whether a pending call can no longer be approved: it is past its
+    /// deadline, or the session it was queued under was revoked, has expired
+    /// or was replaced by a later one.
+    fn is_lapsed(item: &MethodQueueItem<u128>, now: u64) -> bool {
+        item.is_past_deadline(now)
+            || WalletService::get_expiry_user(&item.user)
+                .is_none_or(|s| s.is_expired(now) || s.timestamp > item.time_stamp)
+    }
+
+    /// Records the outcome of an `Executing` call. A call the sweep has
+    /// failed in the meantime keeps its failure, and the late outcome goes to
+    /// the audit log instead.
+    pub fn finish_queue_method(
+        hash: &str,
+        result: Result<CallResult, String>,
+        batch_results: Option<Vec<CallResult>>,
+    ) -> OwnerReply {
+        let now = api::time();
+        CALL_QUEUE.with(|m| {
+            let mut queue = m.borrow_mut();
+            match queue.get(&hash.to_string()) {
+                Some(item) if item.status() != QueueStatus::Executing => {
+                    WalletService::add_audit_entry(AuditEntry {
+                        id: 0,
+                        caller: item.user,
+                        canister: item.payload.canister,
+                        method_name: item.payload.method_name.clone(),
+                        arg_hash: WalletService::audit_arg_hash(&item.payload.args),
+                        cycles: item.payload.cycles,
+                        cycles_refunded: 0,
+                        outcome: AuditOutcome::LateReply {
+                            error: result.err(),
+                        },
+                        time: now,
+                        queue_id: Some(hash.to_string()),
+                    });
+                    item.owner_reply
+                }
+                item => {
+                    let status = match result {
+                        Ok(_) => QueueStatus::Approved,
+                        Err(_) => QueueStatus::Failed,
+                    };
+                    let reply = OwnerReply::Approved(result);
+                    if let Some(mut item) = item {
+                        item.status = Some(status);
+                        item.updated_at = Some(now);
+                        item.owner_reply = reply.clone();
+                        item.batch_results = batch_results;
+                        certified::certify_queue_item(&item);
+                        queue.insert(hash.to_string(), item);
+                    }
+                    reply
+                }
+            }
+        })
+    }
+
+    /// Expires lapsed pending calls, fails calls stuck executing
+    /// and drops finished calls that have been kept for the retention period.
+    /// Looks at up to `QUEUE_SWEEP_BATCH` calls after where the previous sweep
+    /// stopped, so a long queue is covered over several sweeps.
+    pub fn sweep_queue(now: u64) {
+        let retention = WalletService::get_setting().queue_retention_period;
+        let cursor = WALLET_STORE.with(|s| s.borrow().sweep_cursor.clone());
+        CALL_QUEUE.with(|m| {
+            let mut queue = m.borrow_mut();
+            let range = match cursor {
+                None => queue.range(..),
+                Some(cursor) => queue.range((Bound::Excluded(cursor), Bound::Unbounded)),
+            };
+            let items = range.take(QUEUE_SWEEP_BATCH).collect_vec();
+            // a short batch reached the end, the next sweep starts over
+            let next_cursor = match items.last() {
+                Some((hash, _)) if items.len() == QUEUE_SWEEP_BATCH => Some(hash.clone()),
+                _ => None,
+            };
+            WALLET_STORE.with(|s| s.borrow_mut().sweep_cursor = next_cursor);
+            for (hash, mut item) in items {
+                match item.status() {
+                    QueueStatus::Pending => {
+                        if WalletService::is_lapsed(&item, now) {
+                            item.status = Some(QueueStatus::Expired);
+                            item.updated_at = Some(now);
+                            certified::certify_queue_item(&item);
+                            queue.insert(hash, item);
+                        }
+                    }
+                    QueueStatus::Executing => {
+                        // the approving call trapped or the upgrade dropped it
+                        let started_at = item.updated_at.unwrap_or(item.time_stamp);
+                        if now.saturating_sub(started_at) >= QUEUE_EXECUTION_TIMEOUT {
+                            item.status = Some(QueueStatus::Failed);
+                            item.updated_at = Some(now);
+                            item.owner_reply = OwnerReply::Approved(Err(
+                                "The call did not finish executing".to_string(),
+                            ));
+                            certified::certify_queue_item(&item);
+                            queue.insert(hash, item);
+                        }
+                    }
+                    _ => {
+                        let finished_at = item.updated_at.unwrap_or(item.time_stamp);
+                        if now.saturating_sub(finished_at) >= retention {
+                            certified::uncertify_queue_item(&hash);
+                            queue.remove(&hash);
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn get_queue_method(hash: String) -> Option<MethodQueueItem<u128>> {
+        CALL_QUEUE.with(|m| m.borrow().get(&hash))
+    }
+
     pub fn get_queue_reply(hash: String) -> Option<OwnerReply> {
         match WalletService::get_queue_method(hash) {
             None => None,
@@ -169,56 +621,281 @@ impl WalletService {
     }
 
     pub fn get_queue_unconfirmed(user: &Principal) -> Vec<QueueHash> {
-        WALLET_STORE.with(|s| {
-            s.borrow()
-                .call_queue
+        CALL_QUEUE.with(|m| {
+            m.borrow()
                 .iter()
-                .filter(|f| f.1.user.eq(user) && f.1.owner_reply != OwnerReply::NotFound)
+                .filter(|f| f.1.user.eq(user) && f.1.status() == QueueStatus::Pending)
                 .map(|d| QueueHash {
                     hash: d.1.hash.clone(),
-                    user: d.1.user.clone(),
-                    time_stamp: d.1.time_stamp.clone(),
+                    user: d.1.user,
+                    time_stamp: d.1.time_stamp,
                 })
                 .collect_vec()
         })
     }
 
+    /// Up to `limit` queued calls matching `filter`, in id order, starting
+    /// after the id `cursor`. Looks at no more than `LIST_SCAN_LIMIT` calls,
+    /// so a page can come back short with a cursor to carry on from.
+    pub fn list_queue(filter: &QueueFilter, cursor: Option<String>, limit: usize) -> QueuePage {
+        let mut items = vec![];
+        let mut examined = 0;
+        let mut last = None;
+        CALL_QUEUE.with(|m| {
+            let queue = m.borrow();
+            let range = match cursor {
+                None => queue.range(..),
+                Some(cursor) => queue.range((Bound::Excluded(cursor), Bound::Unbounded)),
+            };
+            for (hash, item) in range.take(LIST_SCAN_LIMIT) {
+                examined += 1;
+                if filter.matches(&item) {
+                    items.push(item);
+                    if items.len() > limit {
+                        break;
+                    }
+                }
+                last = Some(hash);
+            }
+        });
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|item| item.hash.clone())
+        } else if examined == LIST_SCAN_LIMIT {
+            last
+        } else {
+            None
+        };
+        QueuePage {
+            items: items
+                .into_iter()
+                .map(|item| QueueItemView {
+                    status: item.status(),
+                    decoded_args: WalletService::decode_queue_args(&item),
+                    item,
+                })
+                .collect(),
+            next_cursor,
+        }
+    }
+
+    /// Appends `entry` to the audit log under the next id. Entries are never
+    /// changed or removed.
+    pub fn add_audit_entry(mut entry: AuditEntry) -> u64 {
+        AUDIT_LOG.with(|l| {
+            let log = l.borrow();
+            entry.id = log.len();
+            certified::certify_audit_entry(&entry);
+            log.append(&entry)
+                .expect("failed to append to the audit log")
+        })
+    }
+
+    pub fn audit_arg_hash(args: &[u8]) -> String {
+        hex::encode(Sha256::digest(args).as_slice())
+    }
+
+    /// Up to `limit` audit entries matching `filter`, from the id `cursor` on.
+    /// Looks at no more than `LIST_SCAN_LIMIT` entries, like `list_queue`.
+    pub fn list_audit_log(filter: &AuditFilter, cursor: Option<u64>, limit: usize) -> AuditPage {
+        let from = cursor.unwrap_or(0);
+        let (mut entries, end, len) = AUDIT_LOG.with(|l| {
+            let log = l.borrow();
+            let end = log.len().min(from.saturating_add(LIST_SCAN_LIMIT as u64));
+            let entries = (from..end)
+                .filter_map(|id| log.get(id))
+                .filter(|entry| filter.matches(entry))
+                .take(limit + 1)
+                .collect_vec();
+            (entries, end, log.len())
+        });
+        let next_cursor = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|entry| entry.id + 1)
+        } else if end < len {
+            Some(end)
+        } else {
+            None
+        };
+        AuditPage {
+            entries,
+            next_cursor,
+        }
+    }
+
+    /// Renders the arguments of a queued call with the signature from the
+    /// caller's `ArgPolicy`, if the session still has one.
+    fn decode_queue_args(item: &MethodQueueItem<u128>) -> Option<String> {
+        let signature = WalletService::get_method(
+            &item.user,
+            &item.payload.canister,
+            &item.payload.method_name,
+        )?
+        .arg_policy?
+        .signature?;
+        policy::decode_args(Some(&signature), &item.payload.args)
+            .ok()
+            .map(|values| IDLArgs::new(&values).to_string())
+    }
+
     pub fn remove_queue_method(hash: String) -> Option<String> {
+        certified::uncertify_queue_item(&hash);
+        CALL_QUEUE.with(|m| m.borrow_mut().remove(&hash).map(|_| hash.clone()))
+    }
+
+    /// Stores the item and returns its id. An id that is already taken is
+    /// re-derived with a fresh nonce, so every queued call gets its own entry.
+    pub fn add_method_queue(mut item: MethodQueueItem<u128>) -> String {
+        CALL_QUEUE.with(|m| {
+            let mut queue = m.borrow_mut();
+            while queue.contains_key(&item.hash) {
+                let nonce = WalletService::next_queue_nonce();
+                item.hash = WalletService::queue_hash(&item, nonce);
+                item.nonce = Some(nonce);
+            }
+            certified::certify_queue_item(&item);
+            queue.insert(item.hash.clone(), item.clone());
+            item.hash
+        })
+    }
+
+    pub fn add_scheduled_call(
+        user: Principal,
+        payload: CallCanisterArgs<u128>,
+        at: u64,
+        interval: Option<u64>,
+    ) -> ScheduledCall {
         WALLET_STORE.with(|s| {
             let mut store = s.borrow_mut();
-            store
-                .call_queue
-                .remove(&hash)
-                .map_or_else(|| None, |_| Some(hash.clone()))
+            store.schedule_nonce += 1;
+            let call = ScheduledCall {
+                id: store.schedule_nonce,
+                user,
+                payload,
+                next_run: Some(at),
+                interval,
+                runs: 0,
+                last_run: None,
+                last_result: None,
+            };
+            store.scheduled_calls.insert(call.id, call.clone());
+            call
+        })
+    }
+
+    pub fn get_scheduled_call(id: u64) -> Option<ScheduledCall> {
+        WALLET_STORE.with(|s| s.borrow().scheduled_calls.get(&id).cloned())
+    }
+
+    /// Scheduled calls of `user`, or all of them for `None`.
+    pub fn list_scheduled_calls(user: Option<&Principal>) -> Vec<ScheduledCall> {
+        WALLET_STORE.with(|s| {
+            s.borrow()
+                .scheduled_calls
+                .values()
+                .filter(|c| user.is_none_or(|u| c.user.eq(u)))
+                .cloned()
+                .collect_vec()
         })
     }
 
-    pub fn add_method_queue(item: MethodQueueItem<u128>) -> String {
+    pub fn remove_scheduled_call(id: u64) -> Option<ScheduledCall> {
+        WALLET_STORE.with(|s| s.borrow_mut().scheduled_calls.remove(&id))
+    }
+
+    pub fn next_scheduled_run() -> Option<u64> {
+        WALLET_STORE.with(|s| {
+            s.borrow()
+                .scheduled_calls
+                .values()
+                .filter_map(|c| c.next_run)
+                .min()
+        })
+    }
+
+    /// Returns the calls due at `now` and moves each to its next run, or ends
+    /// it if it isn't recurring, so a call is never picked up twice for one run.
+    pub fn take_due_scheduled_calls(now: u64) -> Vec<ScheduledCall> {
         WALLET_STORE.with(|s| {
             let mut store = s.borrow_mut();
-            store
-                .call_queue
-                .entry(item.hash.clone())
-                .or_insert(item.clone());
-            item.hash.clone()
+            let mut due = vec![];
+            for call in store.scheduled_calls.values_mut() {
+                let at = match call.next_run {
+                    Some(at) if at <= now => at,
+                    _ => continue,
+                };
+                // runs missed while the wallet was busy or stopped are skipped
+                call.next_run = call
+                    .interval
+                    .map(|i| at.saturating_add(i.saturating_mul((now - at) / i + 1)));
+                call.runs += 1;
+                call.last_run = Some(now);
+                due.push(call.clone());
+            }
+            due
         })
     }
 
-    pub fn get_expiry_user(user: &Principal) -> Option<ExpiryUser> {
+    /// Stores the outcome of a run. A call that won't run again, because it
+    /// isn't recurring or its user is no longer a delegate, is dropped instead;
+    /// its runs stay in the audit log.
+    pub fn record_scheduled_result(id: u64, result: ProxyCallResult) {
         WALLET_STORE.with(|s| {
-            let store = s.borrow();
-            store
-                .expiry_users
-                .get(&user.clone())
-                .map_or_else(|| None, |s| Some(s.clone()))
+            let mut store = s.borrow_mut();
+            let done = match store.scheduled_calls.get_mut(&id) {
+                None => return,
+                Some(call) => {
+                    if let ProxyCallResult::Unauthorized {
+                        reason: Denial::NotDelegate | Denial::SessionExpired { .. },
+                    } = result
+                    {
+                        call.next_run = None;
+                    }
+                    call.last_result = Some(result);
+                    call.next_run.is_none()
+                }
+            };
+            if done {
+                store.scheduled_calls.remove(&id);
+            }
         })
     }
 
+    pub fn count_scheduled_calls(user: &Principal) -> usize {
+        WALLET_STORE.with(|s| {
+            s.borrow()
+                .scheduled_calls
+                .values()
+                .filter(|c| c.user.eq(user))
+                .count()
+        })
+    }
+
+    /// Ends the sessions that expired among the users of scheduled calls,
+    /// which drops their calls.
+    pub fn prune_scheduled_calls() {
+        let users = WALLET_STORE.with(|s| {
+            s.borrow()
+                .scheduled_calls
+                .values()
+                .map(|c| c.user)
+                .unique()
+                .collect_vec()
+        });
+        for user in users {
+            WalletService::remove_if_expiry(&user);
+        }
+    }
+
+    pub fn get_expiry_user(user: &Principal) -> Option<ExpiryUser> {
+        EXPIRY_USERS.with(|m| m.borrow().get(&PrincipalKey(*user)))
+    }
+
     pub fn is_valid_user(user: &Principal) -> bool {
         match WalletService::get_expiry_user(user) {
             None => false,
             Some(r) => {
-                if r.timestamp + WalletService::get_setting().expiry_period < api::time() {
+                if r.is_expired(api::time()) {
                     WalletService::remove_expiry_user(user);
                     false
                 } else {
@@ -228,6 +905,33 @@ impl WalletService {
         }
     }
 
+    /// Moves the end of a live session `period` nanoseconds later.
+    pub fn extend_expiry_user(user: &Principal, period: u64) -> Option<ExpiryUser> {
+        WalletService::update_expiry_timestamp(user, |ts| ts.saturating_add(period))
+    }
+
+    /// Moves the end of a live session `period` nanoseconds earlier. A session
+    /// shortened into the past is expired and removed on its next use.
+    pub fn shorten_expiry_user(user: &Principal, period: u64) -> Option<ExpiryUser> {
+        WalletService::update_expiry_timestamp(user, |ts| ts.saturating_sub(period))
+    }
+
+    fn update_expiry_timestamp(
+        user: &Principal,
+        update: impl FnOnce(u64) -> u64,
+    ) -> Option<ExpiryUser> {
+        if !WalletService::is_valid_user(user) {
+            return None;
+        }
+        let mut expiry_user = WalletService::get_expiry_user(user)?;
+        expiry_user.expiry_timestamp = update(expiry_user.expiry_timestamp);
+        EXPIRY_USERS.with(|m| {
+            m.borrow_mut()
+                .insert(PrincipalKey(*user), expiry_user.clone())
+        });
+        Some(expiry_user)
+    }
+
     pub fn add_proxy_black_list(target: Principal) -> String {
         WALLET_STORE.with(|s| {
             let mut store = s.borrow_mut();
@@ -260,6 +964,52 @@ impl WalletService {
         })
     }
 
+    pub fn set_queue_approval_period(period: u64) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.settings.queue_approval_period = period;
+        })
+    }
+
+    pub fn set_queue_retention_period(period: u64) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.settings.queue_retention_period = period;
+        })
+    }
+
+    /// Replaces the approvers and the number of them that must approve a queued call.
+    pub fn set_approvers(approvers: Vec<Principal>, threshold: u32) -> Result<(), String> {
+        if !approvers.is_empty() && (threshold == 0 || threshold as usize > approvers.len()) {
+            return Err(format!(
+                "Threshold {} is not between 1 and {} approvers",
+                threshold,
+                approvers.len()
+            ));
+        }
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.settings.approvers = approvers.into_iter().collect();
+            store.settings.approval_threshold = threshold.max(1);
+        });
+        Ok(())
+    }
+
+    pub fn set_key_operation_threshold(threshold: Option<u32>) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.settings.key_operation_threshold = threshold;
+        })
+    }
+
+    pub fn has_approvers() -> bool {
+        WALLET_STORE.with(|s| !s.borrow().settings.approvers.is_empty())
+    }
+
+    pub fn is_approver(user: &Principal) -> bool {
+        WALLET_STORE.with(|s| s.borrow().settings.approvers.contains(user))
+    }
+
     pub fn set_method_validate_type(v_type: MethodValidationType) {
         WALLET_STORE.with(|s| {
             let mut store = s.borrow_mut();
@@ -274,18 +1024,21 @@ impl WalletService {
         })
     }
 
+    /// Ends the session of `user` and drops the calls they scheduled.
     pub fn remove_expiry_user(user: &Principal) -> Option<ExpiryUser> {
-        WALLET_STORE.with(|s| {
-            let mut store = s.borrow_mut();
-            store.expiry_users.remove(user)
-        })
+        let removed = EXPIRY_USERS.with(|m| m.borrow_mut().remove(&PrincipalKey(*user)));
+        if removed.is_some() {
+            WALLET_STORE.with(|s| {
+                s.borrow_mut()
+                    .scheduled_calls
+                    .retain(|_, c| c.user != *user)
+            });
+        }
+        removed
     }
 
     pub fn remove_all_expiries() {
-        let all_users = WALLET_STORE.with(|s| {
-            let store = s.borrow();
-            store.expiry_users.values().map(|d| d.clone()).collect_vec()
-        });
+        let all_users = EXPIRY_USERS.with(|m| m.borrow().iter().map(|(_, v)| v).collect_vec());
 
         for i in all_users.iter() {
             WalletService::remove_if_expiry(&i.user)
@@ -293,14 +1046,181 @@ impl WalletService {
     }
 
     pub fn remove_if_expiry(user: &Principal) {
-        WalletService::get_expiry_user(user).map_or_else(
-            || (),
-            |f| {
-                if ic_cdk::api::time() < f.timestamp + WalletService::get_setting().expiry_period {
-                    WalletService::remove_expiry_user(user);
+        if let Some(f) = WalletService::get_expiry_user(user) {
+            if f.is_expired(api::time()) {
+                WalletService::remove_expiry_user(user);
+            }
+        }
+    }
+
+    pub fn set_rate_limit(limit: Option<RateLimit>) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.settings.rate_limit = limit;
+        })
+    }
+
+    pub fn get_rate_limit() -> Option<RateLimit> {
+        WALLET_STORE.with(|s| s.borrow().settings.rate_limit.clone())
+    }
+
+    /// Checks one more call counted under `key` against `limit`.
+    pub fn check_rate(key: &RateKey, limit: &RateLimit, now: u64) -> Result<(), Denial> {
+        WALLET_STORE.with(|s| {
+            let store = s.borrow();
+            let calls = store
+                .rate_counters
+                .get(key)
+                .map_or(&[][..], |c| c.as_slice());
+            limit.check(calls, now)
+        })
+    }
+
+    /// Counts a call made at `now` under each of `keys`.
+    pub fn record_call(keys: impl IntoIterator<Item = RateKey>, now: u64) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            for key in keys {
+                let calls = store.rate_counters.entry(key).or_default();
+                calls.retain(|t| now.saturating_sub(*t) < DAY);
+                calls.push(now);
+            }
+        })
+    }
+
+    /// Drops the latest count made at `now` under each of `keys`, for a call
+    /// that was counted but neither ran nor was queued.
+    pub fn forget_call(keys: impl IntoIterator<Item = RateKey>, now: u64) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            for key in keys {
+                if let Some(calls) = store.rate_counters.get_mut(&key) {
+                    if let Some(index) = calls.iter().rposition(|t| *t == now) {
+                        calls.remove(index);
+                    }
+                    if calls.is_empty() {
+                        store.rate_counters.remove(&key);
+                    }
                 }
-            },
-        );
+            }
+        })
+    }
+
+    /// Drops counts older than a day, the longest window a limit can have.
+    pub fn prune_rate_counters(now: u64) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.rate_counters.retain(|_, calls| {
+                calls.retain(|t| now.saturating_sub(*t) < DAY);
+                !calls.is_empty()
+            });
+        })
+    }
+
+    pub fn list_rate_counters(now: u64) -> Vec<RateCounter> {
+        let count = |calls: &[u64], window: u64| {
+            calls
+                .iter()
+                .filter(|t| now.saturating_sub(**t) < window)
+                .count() as u32
+        };
+        WALLET_STORE.with(|s| {
+            s.borrow()
+                .rate_counters
+                .iter()
+                .map(|(key, calls)| RateCounter {
+                    key: key.clone(),
+                    last_minute: count(calls, MINUTE),
+                    last_hour: count(calls, HOUR),
+                    last_day: count(calls, DAY),
+                })
+                .collect()
+        })
+    }
+
+    /// Tokens `user` sent on `ledger` in the last day.
+    pub fn spent_today(user: &Principal, ledger: &Principal, now: u64) -> u128 {
+        WALLET_STORE.with(|s| {
+            s.borrow()
+                .token_spends
+                .get(&(*user, *ledger))
+                .into_iter()
+                .flatten()
+                .filter(|spend| now.saturating_sub(spend.time) < DAY)
+                .map(|spend| spend.amount)
+                .fold(0u128, |total, amount| total.saturating_add(amount))
+        })
+    }
+
+    pub fn record_spend(user: &Principal, ledger: &Principal, spend: TokenSpend) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            let spends = store.token_spends.entry((*user, *ledger)).or_default();
+            spends.retain(|t| spend.time.saturating_sub(t.time) < DAY);
+            spends.push(spend);
+        })
+    }
+
+    /// Drops the latest spend of `amount`, for a transfer that didn't happen.
+    pub fn release_spend(user: &Principal, ledger: &Principal, amount: u128) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            if let Some(spends) = store.token_spends.get_mut(&(*user, *ledger)) {
+                if let Some(index) = spends.iter().rposition(|t| t.amount == amount) {
+                    spends.remove(index);
+                }
+            }
+        })
+    }
+
+    /// Drops spends older than a day.
+    pub fn prune_token_spends(now: u64) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.token_spends.retain(|_, spends| {
+                spends.retain(|t| now.saturating_sub(t.time) < DAY);
+                !spends.is_empty()
+            });
+        })
+    }
+
+    /// Keeps `approval` as the wallet's allowance for its spender, replacing
+    /// an earlier one. An allowance of zero is dropped.
+    pub fn record_approval(approval: Approval) {
+        WALLET_STORE.with(|s| {
+            let mut store = s.borrow_mut();
+            store.approvals.retain(|a| {
+                !a.is_for(
+                    &approval.ledger,
+                    &approval.from_subaccount,
+                    &approval.spender,
+                )
+            });
+            if approval.amount > 0 {
+                store.approvals.push(approval);
+            }
+        })
+    }
+
+    pub fn list_approvals() -> Vec<Approval> {
+        WALLET_STORE.with(|s| s.borrow().approvals.clone())
+    }
+
+    /// Drops allowances that expired before `now`.
+    pub fn prune_approvals(now: u64) {
+        WALLET_STORE.with(|s| {
+            s.borrow_mut()
+                .approvals
+                .retain(|a| a.expires_at.is_none_or(|t| t > now))
+        })
+    }
+
+    pub fn get_certification_backfill() -> Option<CertificationBackfill> {
+        WALLET_STORE.with(|s| s.borrow().certification_backfill.clone())
+    }
+
+    pub fn set_certification_backfill(backfill: Option<CertificationBackfill>) {
+        WALLET_STORE.with(|s| s.borrow_mut().certification_backfill = backfill)
     }
 
     fn get_setting() -> Settings {
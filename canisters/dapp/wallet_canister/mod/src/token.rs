@@ -0,0 +1,343 @@
+use crate::service::WalletService;
+use crate::types::{
+    Account, Approval, CallCanisterArgs, Denial, ExpiryUser, TokenAllowance, TokenPolicy,
+    TokenSpend,
+};
+use candid::parser::value::IDLValue;
+use candid::{CandidType, Decode, Encode, IDLArgs, Nat};
+use ic_cdk::export::Principal;
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha224};
+
+pub const ICRC1_TRANSFER: &str = "icrc1_transfer";
+/// Methods of the legacy ICP ledger interface.
+pub const LEGACY_TRANSFER: &str = "transfer";
+pub const SEND_DFX: &str = "send_dfx";
+pub const ICRC2_APPROVE: &str = "icrc2_approve";
+
+/// Argument of `icrc1_transfer`.
+#[derive(CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<ByteBuf>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<ByteBuf>,
+    pub created_at_time: Option<u64>,
+}
+
+/// Argument of `icrc2_approve`.
+#[derive(CandidType, Deserialize)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<ByteBuf>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<ByteBuf>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Tokens {
+    pub e8s: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TimeStamp {
+    pub timestamp_nanos: u64,
+}
+
+/// Argument of the legacy ledger's `transfer`, which pays to an account identifier.
+#[derive(CandidType, Deserialize)]
+pub struct LegacyTransferArgs {
+    pub memo: u64,
+    pub amount: Tokens,
+    pub fee: Tokens,
+    pub from_subaccount: Option<ByteBuf>,
+    pub to: ByteBuf,
+    pub created_at_time: Option<TimeStamp>,
+}
+
+/// Argument of the legacy ledger's `send_dfx`, which takes the account
+/// identifier as hex.
+#[derive(CandidType, Deserialize)]
+pub struct SendArgs {
+    pub memo: u64,
+    pub amount: Tokens,
+    pub fee: Tokens,
+    pub from_subaccount: Option<ByteBuf>,
+    pub to: String,
+    pub created_at_time: Option<TimeStamp>,
+}
+
+/// Tokens leaving the wallet in one call, fee included. ICRC-1 accounts are
+/// compared by account identifier, so both ledger interfaces share the
+/// recipient allowlist.
+pub struct Transfer {
+    pub to: AccountIdentifier,
+    pub amount: u128,
+}
+
+pub type AccountIdentifier = [u8; 32];
+
+/// The ICP ledger's account identifier: a CRC32 checksum followed by the
+/// SHA-224 of the owner and subaccount.
+pub fn account_identifier(account: &Account) -> AccountIdentifier {
+    let mut hasher = Sha224::new();
+    hasher.update(b"\x0Aaccount-id");
+    hasher.update(account.owner.as_slice());
+    hasher.update(account.subaccount_bytes());
+    let hash = hasher.finalize();
+    let mut id = [0u8; 32];
+    id[..4].copy_from_slice(&crc32fast::hash(&hash).to_be_bytes());
+    id[4..].copy_from_slice(&hash);
+    id
+}
+
+pub fn parse_account_identifier(bytes: &[u8]) -> Result<AccountIdentifier, String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("Account identifier is {} bytes, not 32", bytes.len()))
+}
+
+/// The policy the delegate has for `ledger`, if any.
+pub fn token_policy<'a>(session: &'a ExpiryUser, ledger: &Principal) -> Option<&'a TokenPolicy> {
+    session
+        .target_list
+        .iter()
+        .filter(|d| d.canister.eq(ledger))
+        .find_map(|d| d.token_policy.as_ref())
+}
+
+/// Decodes the transfer `args` makes, or `None` when the call moves no tokens.
+pub fn decode_transfer(
+    policy: &TokenPolicy,
+    args: &CallCanisterArgs<u128>,
+) -> Result<Option<Transfer>, String> {
+    match args.method_name.as_str() {
+        ICRC1_TRANSFER => {
+            let arg = Decode!(&args.args, TransferArg)
+                .map_err(|e| format!("Invalid transfer argument: {}", e))?;
+            let fee = arg.fee.map_or(Ok(policy.fee), to_u128)?;
+            Ok(Some(Transfer {
+                to: account_identifier(&arg.to),
+                amount: with_fee(to_u128(arg.amount)?, fee)?,
+            }))
+        }
+        LEGACY_TRANSFER => {
+            let arg = Decode!(&args.args, LegacyTransferArgs)
+                .map_err(|e| format!("Invalid transfer argument: {}", e))?;
+            Ok(Some(Transfer {
+                to: parse_account_identifier(&arg.to)?,
+                amount: with_fee(arg.amount.e8s as u128, arg.fee.e8s as u128)?,
+            }))
+        }
+        SEND_DFX => {
+            let arg = Decode!(&args.args, SendArgs)
+                .map_err(|e| format!("Invalid transfer argument: {}", e))?;
+            let to = hex::decode(&arg.to)
+                .map_err(|e| format!("Invalid account identifier {}: {}", arg.to, e))?;
+            Ok(Some(Transfer {
+                to: parse_account_identifier(&to)?,
+                amount: with_fee(arg.amount.e8s as u128, arg.fee.e8s as u128)?,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn with_fee(amount: u128, fee: u128) -> Result<u128, String> {
+    amount
+        .checked_add(fee)
+        .ok_or_else(|| "Transfer amount is too large".to_string())
+}
+
+fn to_u128(n: Nat) -> Result<u128, String> {
+    u128::try_from(&n.0).map_err(|_| format!("Amount {} is too large", n))
+}
+
+/// Holds a transfer or approval to the policy of its ledger, given what the
+/// delegate already spent there in the last day. Approvals are checked with
+/// or without a policy.
+pub fn check_token_call(
+    session: &ExpiryUser,
+    args: &CallCanisterArgs<u128>,
+    now: u64,
+) -> Result<(), Denial> {
+    if args.method_name == ICRC2_APPROVE {
+        return check_approve(session, token_policy(session, &args.canister), args);
+    }
+    let policy = match token_policy(session, &args.canister) {
+        None => return Ok(()),
+        Some(policy) => policy,
+    };
+    let transfer = match decode_transfer(policy, args)
+        .map_err(|reason| Denial::TransferRejected { reason })?
+    {
+        None => return Ok(()),
+        Some(transfer) => transfer,
+    };
+    if let Some(recipients) = &policy.recipients {
+        if !recipients
+            .iter()
+            .any(|r| account_identifier(r) == transfer.to)
+        {
+            return Err(Denial::TransferRejected {
+                reason: format!("Recipient {} is not allowed", hex::encode(transfer.to)),
+            });
+        }
+    }
+    match remaining(session, &args.canister, policy, now) {
+        Some(remaining) if transfer.amount > remaining => Err(Denial::AllowanceExceeded {
+            requested: transfer.amount,
+            remaining,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// An approval must expire no later than the delegate's session, so the
+/// allowance can't outlive the delegation. Under a policy it must also name
+/// an allowed spender and stay under `max_approval`.
+fn check_approve(
+    session: &ExpiryUser,
+    policy: Option<&TokenPolicy>,
+    args: &CallCanisterArgs<u128>,
+) -> Result<(), Denial> {
+    let rejected = |reason: String| Denial::TransferRejected { reason };
+    let arg = Decode!(&args.args, ApproveArgs)
+        .map_err(|e| rejected(format!("Invalid approve argument: {}", e)))?;
+    match arg.expires_at {
+        Some(expires_at) if expires_at <= session.expiry_timestamp => {}
+        _ => {
+            return Err(rejected(format!(
+                "Approval must expire by the end of the session at {}",
+                session.expiry_timestamp
+            )))
+        }
+    }
+    let policy = match policy {
+        None => return Ok(()),
+        Some(policy) => policy,
+    };
+    if let Some(spenders) = &policy.spenders {
+        let spender = account_identifier(&arg.spender);
+        if !spenders.iter().any(|s| account_identifier(s) == spender) {
+            return Err(rejected(format!(
+                "Spender {} is not allowed",
+                arg.spender.owner
+            )));
+        }
+    }
+    let amount = to_u128(arg.amount).map_err(rejected)?;
+    match policy.max_approval {
+        Some(max) if amount > max => Err(Denial::AllowanceExceeded {
+            requested: amount,
+            remaining: max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Keeps track of the allowance an `icrc2_approve` call granted, once the
+/// ledger accepted it.
+pub fn track_approval(caller: &Principal, args: &CallCanisterArgs<u128>, reply: &[u8], now: u64) {
+    if args.method_name != ICRC2_APPROVE || transfer_failed(reply) {
+        return;
+    }
+    if let Ok(arg) = Decode!(&args.args, ApproveArgs) {
+        WalletService::record_approval(Approval {
+            ledger: args.canister,
+            from_subaccount: arg.from_subaccount,
+            spender: arg.spender,
+            amount: u128::try_from(&arg.amount.0).unwrap_or(u128::MAX),
+            expires_at: arg.expires_at,
+            approved_by: *caller,
+            time: now,
+        });
+    }
+}
+
+/// The call that sets `approval` back to zero.
+pub fn revoke_call(approval: &Approval) -> CallCanisterArgs<u128> {
+    let arg = ApproveArgs {
+        from_subaccount: approval.from_subaccount.clone(),
+        spender: approval.spender.clone(),
+        amount: Nat::from(0u64),
+        expected_allowance: None,
+        expires_at: None,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    CallCanisterArgs {
+        canister: approval.ledger,
+        method_name: ICRC2_APPROVE.to_string(),
+        args: Encode!(&arg).expect("approve arguments encode"),
+        cycles: 0,
+    }
+}
+
+/// Most the delegate may still send in one transfer on `ledger`.
+fn remaining(
+    session: &ExpiryUser,
+    ledger: &Principal,
+    policy: &TokenPolicy,
+    now: u64,
+) -> Option<u128> {
+    let today = policy
+        .max_per_day
+        .map(|max| max.saturating_sub(WalletService::spent_today(&session.user, ledger, now)));
+    match (policy.max_per_transfer, today) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Counts a transfer the caller was let through against their allowance.
+pub fn record_transfer(caller: &Principal, args: &CallCanisterArgs<u128>, now: u64) {
+    if let Some(amount) = transfer_amount(caller, args) {
+        WalletService::record_spend(caller, &args.canister, TokenSpend { time: now, amount });
+    }
+}
+
+/// Gives back the allowance of a transfer the ledger did not make.
+pub fn release_transfer(caller: &Principal, args: &CallCanisterArgs<u128>) {
+    if let Some(amount) = transfer_amount(caller, args) {
+        WalletService::release_spend(caller, &args.canister, amount);
+    }
+}
+
+fn transfer_amount(caller: &Principal, args: &CallCanisterArgs<u128>) -> Option<u128> {
+    let session = WalletService::get_expiry_user(caller)?;
+    let policy = token_policy(&session, &args.canister)?;
+    Some(decode_transfer(policy, args).ok()??.amount)
+}
+
+/// Whether a ledger replied with the `Err` case of its result, meaning no
+/// tokens moved.
+pub fn transfer_failed(reply: &[u8]) -> bool {
+    match IDLArgs::from_bytes(reply).map(|a| a.args) {
+        Ok(values) => matches!(
+            values.first(),
+            Some(IDLValue::Variant(v)) if v.0.id.get_id() == candid::idl_hash("Err")
+        ),
+        Err(_) => false,
+    }
+}
+
+pub fn allowance(session: &ExpiryUser, ledger: &Principal, now: u64) -> Option<TokenAllowance> {
+    let policy = token_policy(session, ledger)?;
+    let spent_today = WalletService::spent_today(&session.user, ledger, now);
+    Some(TokenAllowance {
+        ledger: *ledger,
+        max_per_transfer: policy.max_per_transfer,
+        spent_today,
+        remaining_today: policy
+            .max_per_day
+            .map(|max| max.saturating_sub(spent_today)),
+    })
+}
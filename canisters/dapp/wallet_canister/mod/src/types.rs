@@ -1,7 +1,14 @@
-use candid::CandidType;
+use candid::{CandidType, Decode, Encode, Nat};
 use ic_cdk::export::Principal;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde_bytes::ByteBuf;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::service::QUEUE_APPROVAL_PERIOD;
 
 #[derive(CandidType, Deserialize, Clone)]
 pub struct CallCanisterArgs<TCycles> {
@@ -12,6 +19,59 @@ pub struct CallCanisterArgs<TCycles> {
     pub cycles: TCycles,
 }
 
+impl From<CallCanisterArgs<u64>> for CallCanisterArgs<u128> {
+    fn from(args: CallCanisterArgs<u64>) -> Self {
+        CallCanisterArgs {
+            canister: args.canister,
+            method_name: args.method_name,
+            args: args.args,
+            cycles: args.cycles as u128,
+        }
+    }
+}
+
+/// Reply of the cycles wallet's `wallet_balance` and `wallet_balance128`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BalanceResult<TCycles> {
+    pub amount: TCycles,
+}
+
+/// Argument of the cycles wallet's `wallet_send`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SendCyclesArgs<TCycles> {
+    pub canister: Principal,
+    pub amount: TCycles,
+}
+
+/// Argument of the cycles wallet's `wallet_receive`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ReceiveOptions {
+    pub memo: Option<String>,
+}
+
+/// Settings of a canister made by `wallet_create_canister`. `controller` is
+/// the older single-controller form dfx may still send.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CanisterSettings {
+    pub controller: Option<Principal>,
+    pub controllers: Option<Vec<Principal>>,
+    pub compute_allocation: Option<Nat>,
+    pub memory_allocation: Option<Nat>,
+    pub freezing_threshold: Option<Nat>,
+}
+
+/// Argument of the cycles wallet's `wallet_create_canister`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CreateCanisterArgs<TCycles> {
+    pub cycles: TCycles,
+    pub settings: CanisterSettings,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CreateResult {
+    pub canister_id: Principal,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub enum MethodType {
     QUERY,
@@ -31,18 +91,305 @@ pub struct Method {
     pub name: String,
     pub method_type: MethodType,
     pub key_operation: bool,
+    pub cycles: Option<CyclesBudget>,
+    pub arg_policy: Option<ArgPolicy>,
+    /// Approvals a queued call to this method needs, overriding the wallet's thresholds.
+    pub approval_threshold: Option<u32>,
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Constraints on the decoded arguments of a method, checked before the call
+/// is forwarded or queued.
+#[derive(CandidType, Serialize, Clone, Deserialize)]
+pub struct ArgPolicy {
+    /// Candid argument types, e.g. `(record { to : principal; amount : nat })`.
+    /// When set, `args` must decode against it. Constraint paths match field
+    /// names by their Candid hash, so they work without a signature too.
+    pub signature: Option<String>,
+    pub constraints: Vec<ArgConstraint>,
+}
+
+#[derive(CandidType, Serialize, Clone, Deserialize)]
+pub struct ArgConstraint {
+    /// Position of the argument the path starts from.
+    pub arg: u32,
+    /// Record or variant field names leading to the constrained value.
+    pub path: Vec<String>,
+    pub rule: ArgRule,
+}
+
+#[derive(CandidType, Serialize, Clone, Deserialize)]
+pub enum ArgRule {
+    PrincipalEq(Principal),
+    PrincipalIn(Vec<Principal>),
+    TextIn(Vec<String>),
+    NatMax(Nat),
+}
+
+/// Cycles a delegate may attach to proxied calls. `total` is what is left and
+/// shrinks with every forwarded call, refunds from the callee are added back.
+#[derive(CandidType, Serialize, Clone, Deserialize)]
+pub struct CyclesBudget {
+    pub total: u128,
+    pub per_call: Option<u128>,
+}
+
+impl CyclesBudget {
+    /// Most cycles a single call may attach right now.
+    pub fn available(&self) -> u128 {
+        self.per_call.map_or(self.total, |max| max.min(self.total))
+    }
+
+    pub fn allows(&self, cycles: u128) -> bool {
+        cycles <= self.available()
+    }
+}
+
+/// Most calls allowed in any sliding minute, hour and day. Unset windows are
+/// not limited.
+#[derive(CandidType, Serialize, Clone, Deserialize)]
+pub struct RateLimit {
+    pub per_minute: Option<u32>,
+    pub per_hour: Option<u32>,
+    pub per_day: Option<u32>,
+}
+
+pub const MINUTE: u64 = 60 * 1000 * 1000 * 1000;
+pub const HOUR: u64 = 60 * MINUTE;
+pub const DAY: u64 = 24 * HOUR;
+
+impl RateLimit {
+    /// Checks one more call at `now` against the times of earlier calls,
+    /// oldest first.
+    pub fn check(&self, calls: &[u64], now: u64) -> Result<(), Denial> {
+        for (limit, window) in [
+            (self.per_minute, MINUTE),
+            (self.per_hour, HOUR),
+            (self.per_day, DAY),
+        ] {
+            let Some(limit) = limit else { continue };
+            let in_window = &calls[calls.partition_point(|t| now.saturating_sub(*t) >= window)..];
+            if in_window.len() >= limit as usize {
+                // there is room again once enough of the calls in the window age out of it
+                let retry_at = in_window
+                    .len()
+                    .checked_sub(limit as usize)
+                    .and_then(|i| in_window.get(i))
+                    .map_or(now, |t| *t)
+                    .saturating_add(window);
+                return Err(Denial::RateLimited {
+                    limit,
+                    window,
+                    retry_at,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An ICRC-1 account.
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<ByteBuf>,
+}
+
+impl Account {
+    pub fn subaccount_bytes(&self) -> [u8; 32] {
+        subaccount_bytes(&self.subaccount)
+    }
+}
+
+/// A missing subaccount is the default, all-zero one.
+pub fn subaccount_bytes(subaccount: &Option<ByteBuf>) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    if let Some(subaccount) = subaccount {
+        let len = subaccount.len().min(32);
+        bytes[..len].copy_from_slice(&subaccount[..len]);
+    }
+    bytes
+}
+
+/// Limits on the tokens a delegate may move out of the wallet on one ICRC-1
+/// or legacy ICP ledger. Amounts are in the ledger's smallest unit and
+/// include the fee.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct TokenPolicy {
+    pub max_per_transfer: Option<u128>,
+    /// Most the delegate may spend in any 24 hours.
+    pub max_per_day: Option<u128>,
+    /// Accounts transfers may go to; any account if unset.
+    pub recipients: Option<Vec<Account>>,
+    /// The ledger's fee, charged for transfers that don't set one.
+    pub fee: u128,
+    /// Most an `icrc2_approve` may let a spender take.
+    pub max_approval: Option<u128>,
+    /// Accounts that may be approved as spenders; any account if unset.
+    pub spenders: Option<Vec<Account>>,
+}
+
+/// Rules for transfers out of an ICRC-7 or EXT NFT collection.
+#[derive(CandidType, Serialize, Clone, Deserialize)]
+pub struct NftPolicy {
+    /// Tokens that may be transferred, as ICRC-7 token ids in decimal or EXT
+    /// token identifiers; any token if unset.
+    pub token_ids: Option<Vec<String>>,
+    /// Transfers left in the session. Like a `CyclesBudget` it shrinks with
+    /// every transfer, and transfers that fail are given back.
+    pub max_transfers: Option<u32>,
+    /// Accounts tokens may go to; any account if unset.
+    pub recipients: Option<Vec<Account>>,
+    /// Queues every transfer for owner approval.
+    pub requires_approval: bool,
+}
+
+/// An ICRC-2 allowance the wallet granted and that has not expired or been
+/// set back to zero, as far as the wallet knows.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Approval {
+    pub ledger: Principal,
+    pub from_subaccount: Option<ByteBuf>,
+    pub spender: Account,
+    pub amount: u128,
+    pub expires_at: Option<u64>,
+    /// Owner or delegate that made the approval.
+    pub approved_by: Principal,
+    pub time: u64,
+}
+
+impl Approval {
+    pub fn is_for(
+        &self,
+        ledger: &Principal,
+        from_subaccount: &Option<ByteBuf>,
+        spender: &Account,
+    ) -> bool {
+        self.ledger == *ledger
+            && subaccount_bytes(&self.from_subaccount) == subaccount_bytes(from_subaccount)
+            && self.spender.owner == spender.owner
+            && self.spender.subaccount_bytes() == spender.subaccount_bytes()
+    }
+}
+
+/// Outcome of setting one `Approval` back to zero.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ApprovalRevocation {
+    pub approval: Approval,
+    /// Why the ledger didn't revoke it, `None` if it did.
+    pub error: Option<String>,
 }
 
+/// What is left of a delegate's `TokenPolicy` on one ledger.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct TokenAllowance {
+    pub ledger: Principal,
+    pub max_per_transfer: Option<u128>,
+    pub spent_today: u128,
+    /// `None` without a daily limit.
+    pub remaining_today: Option<u128>,
+}
+
+/// Tokens a delegate sent at `time`, fee included.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct TokenSpend {
+    pub time: u64,
+    pub amount: u128,
+}
+
+/// What a rate limit counts calls for.
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RateKey {
+    /// Every delegate call, see `Settings::rate_limit`.
+    Wallet,
+    Delegate(Principal),
+    Target(Principal, Principal),
+    /// Keyed by the `methods` entry that grants the call, so all methods
+    /// granted by one pattern share its limit.
+    Method(Principal, Principal, String),
+}
+
+/// Calls counted under `key` in the last minute, hour and day.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RateCounter {
+    pub key: RateKey,
+    pub last_minute: u32,
+    pub last_hour: u32,
+    pub last_day: u32,
+}
+
+/// The methods a delegate may call on one canister. Keys of `methods` are
+/// method names or patterns where `*` stands for any run of characters, such
+/// as `icrc1_*`.
 #[derive(CandidType, Serialize, Clone, Deserialize)]
 pub struct ProxyActorItem {
     pub canister: Principal,
     pub methods: BTreeMap<String, Method>,
+    /// Limits calls to all methods of this canister together.
+    pub rate_limit: Option<RateLimit>,
+    /// Method names and patterns that are never allowed, whatever `methods`
+    /// or `all_queries` say.
+    pub deny: Option<Vec<String>>,
+    /// Lets `proxy_query` call any method of the canister. Only queries get
+    /// this grant: the wallet can't tell an update method from a query, but
+    /// the IC rejects an update method called as a query.
+    pub all_queries: Option<bool>,
+    /// Makes the canister an ICRC-1 ledger whose transfers are held to this policy.
+    pub token_policy: Option<TokenPolicy>,
+    /// Makes the canister an ICRC-7 or EXT collection whose transfers are held
+    /// to this policy. EXT and the legacy ICP ledger both name their method
+    /// `transfer`, so a target should have one policy or the other.
+    pub nft_policy: Option<NftPolicy>,
+}
+
+impl ProxyActorItem {
+    pub fn denies(&self, method_name: &str) -> bool {
+        self.deny
+            .iter()
+            .flatten()
+            .any(|p| matches_pattern(p, method_name))
+    }
+
+    /// Key of the entry in `methods` for `method_name`: the exact entry if
+    /// there is one, otherwise the longest pattern that matches it.
+    pub fn method_key(&self, method_name: &str) -> Option<&str> {
+        if let Some((key, _)) = self.methods.get_key_value(method_name) {
+            return Some(key);
+        }
+        self.methods
+            .keys()
+            .filter(|k| k.contains('*') && matches_pattern(k, method_name))
+            .max_by_key(|k| k.len())
+            .map(|k| k.as_str())
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of characters.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = name.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
 }
 
 #[derive(CandidType, Serialize, Clone, Deserialize)]
 pub struct ProxyActorTargets {
     pub expiration: Option<u64>,
     pub targets: Vec<ProxyActorItem>,
+    pub cycles: Option<CyclesBudget>,
+    /// Limits all calls of the session together.
+    pub rate_limit: Option<RateLimit>,
 }
 
 #[derive(CandidType, Deserialize, Clone, PartialEq)]
@@ -51,28 +398,646 @@ pub struct CallResult {
     pub r#return: Vec<u8>,
 }
 
+/// Outcome of `proxy_call`.
+#[derive(CandidType, Deserialize, Clone, PartialEq)]
+pub enum ProxyCallResult {
+    /// The target replied.
+    Executed(CallResult),
+    /// The call waits for owner approval under `id`.
+    Queued { id: String, expires_at: Option<u64> },
+    /// The target, or the system on its behalf, rejected the call.
+    Rejected { reject_code: u8, message: String },
+    /// The wallet refused to forward the call.
+    Unauthorized { reason: Denial },
+    /// A one-way call was sent; `id` is its audit log entry. No reply comes back.
+    Submitted { id: u64 },
+}
+
+/// One call of `proxy_call_batch`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BatchCall {
+    pub call: CallCanisterArgs<u128>,
+    /// Index of an earlier call in the batch whose reply bytes replace `call.args`.
+    pub args_from: Option<u32>,
+}
+
+impl BatchCall {
+    /// The call with its arguments taken from `replies` when it refers to an
+    /// earlier call, `None` if that call has no reply.
+    pub fn resolve(&self, replies: &[CallResult]) -> Option<CallCanisterArgs<u128>> {
+        let mut call = self.call.clone();
+        if let Some(index) = self.args_from {
+            call.args = replies.get(index as usize)?.r#return.clone();
+        }
+        Some(call)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BatchMode {
+    /// A call that needs approval only once its arguments are taken from an
+    /// earlier reply has the rest of the batch queued from there.
+    Sequential,
+    /// A call that needs approval only once its arguments are taken from an
+    /// earlier reply stops the batch with `ApprovalRequired`, since the calls
+    /// before it have run and can't be queued with it.
+    AllOrNothing,
+}
+
+/// Outcome of `proxy_call_batch`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct BatchResult {
+    /// Outcome of every call that ran, followed by the outcome that stopped
+    /// the batch, if any. A one-way call counts as ran with an empty reply.
+    pub results: Vec<ProxyCallResult>,
+    /// Index of the call that was denied, rejected or queued; `None` when
+    /// every call ran.
+    pub stopped_at: Option<u32>,
+}
+
+/// What the wallet does with a call, see `auth::check_call`.
+#[derive(CandidType, Deserialize, Clone, PartialEq)]
+pub enum Decision {
+    /// The call is forwarded right away.
+    Execute,
+    /// The call waits for owner approval.
+    Queue,
+    Deny(Denial),
+}
+
+/// Why the wallet refused to forward a call.
+#[derive(CandidType, Deserialize, Clone, PartialEq)]
+pub enum Denial {
+    /// The caller is neither the owner nor a delegate.
+    NotDelegate,
+    SessionExpired {
+        expired_at: u64,
+    },
+    CanisterBlacklisted,
+    /// The canister is not in the delegate's targets.
+    CanisterNotAllowed,
+    /// The method is not in the delegate's targets for this canister.
+    MethodNotAllowed,
+    /// The arguments break the method's `ArgPolicy`.
+    ArgumentRejected {
+        reason: String,
+    },
+    /// The attached cycles exceed what the session or method budget allows.
+    BudgetExceeded {
+        requested: u128,
+        available: u128,
+    },
+    /// `limit` calls were already made in the last `window` nanoseconds.
+    RateLimited {
+        limit: u32,
+        window: u64,
+        retry_at: u64,
+    },
+    /// `proxy_query` was asked for a method not registered as a query.
+    NotAQuery,
+    /// The call needs approval, which `proxy_query` can't wait for.
+    ApprovalRequired,
+    /// A token or NFT transfer breaks its policy other than by its amount.
+    TransferRejected {
+        reason: String,
+    },
+    /// A token transfer, fee included, is more than the `TokenPolicy` allows,
+    /// or a call moves more NFTs than the session has transfers left.
+    AllowanceExceeded {
+        requested: u128,
+        remaining: u128,
+    },
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denial::NotDelegate => write!(f, "caller is not a delegate"),
+            Denial::SessionExpired { expired_at } => write!(f, "session expired at {}", expired_at),
+            Denial::CanisterBlacklisted => write!(f, "canister is in proxy black list"),
+            Denial::CanisterNotAllowed => write!(f, "canister is not in authorized targets"),
+            Denial::MethodNotAllowed => write!(f, "method is not in authorized targets"),
+            Denial::ArgumentRejected { reason } => write!(f, "{}", reason),
+            Denial::BudgetExceeded {
+                requested,
+                available,
+            } => write!(
+                f,
+                "{} cycles requested, {} available in the budget",
+                requested, available
+            ),
+            Denial::RateLimited {
+                limit, retry_at, ..
+            } => write!(
+                f,
+                "rate limit of {} calls reached, retry at {}",
+                limit, retry_at
+            ),
+            Denial::NotAQuery => write!(f, "method is not a query"),
+            Denial::ApprovalRequired => write!(f, "call requires approval"),
+            Denial::TransferRejected { reason } => write!(f, "{}", reason),
+            Denial::AllowanceExceeded {
+                requested,
+                remaining,
+            } => write!(
+                f,
+                "{} tokens requested, {} left in the allowance",
+                requested, remaining
+            ),
+        }
+    }
+}
+
 #[derive(CandidType, Serialize, Clone, Deserialize)]
 pub struct ExpiryUser {
     pub user: Principal,
     pub target_list: Vec<ProxyActorItem>,
     pub timestamp: u64,
     pub expiry_timestamp: u64,
+    pub cycles: Option<CyclesBudget>,
+    pub rate_limit: Option<RateLimit>,
 }
 
-#[derive(CandidType, Clone)]
-pub struct WalletStore<TCycles> {
-    pub expiry_users: BTreeMap<Principal, ExpiryUser>,
+impl ExpiryUser {
+    /// A session is valid up to, but not including, its `expiry_timestamp`.
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expiry_timestamp
+    }
+
+    fn denies(&self, canister: &Principal, method_name: &str) -> bool {
+        self.target_list
+            .iter()
+            .any(|d| d.canister.eq(canister) && d.denies(method_name))
+    }
+
+    /// The entry that lets the delegate call `method_name` on `canister`, if
+    /// no target of the canister denies it.
+    pub fn method(&self, canister: &Principal, method_name: &str) -> Option<&Method> {
+        self.method_entry(canister, method_name).map(|(_, m)| m)
+    }
+
+    /// `method` along with its key in `methods`, which is a pattern when the
+    /// method has no entry of its own.
+    pub fn method_entry(&self, canister: &Principal, method_name: &str) -> Option<(&str, &Method)> {
+        if self.denies(canister, method_name) {
+            return None;
+        }
+        self.target_list
+            .iter()
+            .filter(|d| d.canister.eq(canister))
+            .find_map(|d| d.methods.get_key_value(d.method_key(method_name)?))
+            .map(|(key, method)| (key.as_str(), method))
+    }
+
+    pub fn method_mut(&mut self, canister: &Principal, method_name: &str) -> Option<&mut Method> {
+        if self.denies(canister, method_name) {
+            return None;
+        }
+        let (target, key) = self
+            .target_list
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.canister.eq(canister))
+            .find_map(|(i, d)| Some((i, d.method_key(method_name)?.to_string())))?;
+        self.target_list[target].methods.get_mut(&key)
+    }
+
+    /// `method` for a call made through `proxy_query`, which also falls back
+    /// to a canister's `all_queries` grant.
+    pub fn query_method(&self, canister: &Principal, method_name: &str) -> Option<Cow<'_, Method>> {
+        if let Some(method) = self.method(canister, method_name) {
+            return Some(Cow::Borrowed(method));
+        }
+        let granted = !self.denies(canister, method_name)
+            && self
+                .target_list
+                .iter()
+                .any(|d| d.canister.eq(canister) && d.all_queries == Some(true));
+        granted.then(|| {
+            Cow::Owned(Method {
+                name: method_name.to_string(),
+                method_type: MethodType::QUERY,
+                key_operation: false,
+                cycles: None,
+                arg_policy: None,
+                approval_threshold: None,
+                rate_limit: None,
+            })
+        })
+    }
+}
+
+/// Heap part of the wallet state. Expiry users and the call queue live in
+/// stable-memory maps, see `service::EXPIRY_USERS` and `service::CALL_QUEUE`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStore {
     pub settings: Settings,
+    /// Last nonce mixed into a queue hash, see `WalletService::queue_hash`.
+    pub queue_nonce: u64,
+    pub scheduled_calls: BTreeMap<u64, ScheduledCall>,
+    /// Last id given to a scheduled call.
+    pub schedule_nonce: u64,
+    /// Times of the calls of the last day, oldest first, for every key that
+    /// had a rate limit when the call was made.
+    pub rate_counters: BTreeMap<RateKey, Vec<u64>>,
+    /// Token transfers of the last day, oldest first, by delegate and ledger.
+    pub token_spends: BTreeMap<(Principal, Principal), Vec<TokenSpend>>,
+    pub approvals: Vec<Approval>,
+    /// `None` once everything stored is certified, see `certified::backfill`.
+    pub certification_backfill: Option<CertificationBackfill>,
+    /// Id of the last queued call `WalletService::sweep_queue` looked at.
+    pub sweep_cursor: Option<String>,
+}
+
+/// How far certification has caught up with the audit entries and queued
+/// calls stored before an upgrade to stable certification.
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct CertificationBackfill {
+    /// Id of the next audit entry to certify.
+    pub audit_next: u64,
+    /// Id of the last queued call certified.
+    pub queue_after: Option<String>,
+}
+
+/// A call the wallet makes by itself at `next_run`, on behalf of `user` and
+/// under the same checks as `proxy_call`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ScheduledCall {
+    pub id: u64,
+    pub user: Principal,
+    pub payload: CallCanisterArgs<u128>,
+    /// `None` while the last run of the call is in flight; the call is
+    /// dropped once it finished.
+    pub next_run: Option<u64>,
+    /// Time between runs of a recurring call.
+    pub interval: Option<u64>,
+    pub runs: u64,
+    pub last_run: Option<u64>,
+    pub last_result: Option<ProxyCallResult>,
+}
+
+/// Layout of `WalletStore` in schema version 9, before certification moved
+/// to stable memory.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV9 {
+    pub settings: Settings,
+    pub queue_nonce: u64,
+    pub scheduled_calls: BTreeMap<u64, ScheduledCall>,
+    pub schedule_nonce: u64,
+    pub rate_counters: BTreeMap<RateKey, Vec<u64>>,
+    pub token_spends: BTreeMap<(Principal, Principal), Vec<TokenSpend>>,
+    pub approvals: Vec<Approval>,
+}
+
+/// Layout of `WalletStore` in schema version 8, before tracked approvals.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV8 {
+    pub settings: Settings,
+    pub queue_nonce: u64,
+    pub scheduled_calls: BTreeMap<u64, ScheduledCall>,
+    pub schedule_nonce: u64,
+    pub rate_counters: BTreeMap<RateKey, Vec<u64>>,
+    pub token_spends: BTreeMap<(Principal, Principal), Vec<TokenSpend>>,
+}
+
+/// Layout of `WalletStore` in schema version 7, before token policies.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV7 {
+    pub settings: Settings,
+    pub queue_nonce: u64,
+    pub scheduled_calls: BTreeMap<u64, ScheduledCall>,
+    pub schedule_nonce: u64,
+    pub rate_counters: BTreeMap<RateKey, Vec<u64>>,
+}
+
+/// Layout of `WalletStore` in schema version 6, before rate limits.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV6 {
+    pub settings: SettingsV3,
+    pub queue_nonce: u64,
+    pub scheduled_calls: BTreeMap<u64, ScheduledCall>,
+    pub schedule_nonce: u64,
+}
+
+/// Layout of `WalletStore` in schema version 5, before scheduled calls.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV5 {
+    pub settings: SettingsV3,
+    pub queue_nonce: u64,
+}
+
+/// Layout of `WalletStore` in schema version 4, before approvers.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV4 {
+    pub settings: SettingsV2,
+    pub queue_nonce: u64,
+}
+
+/// Layout of `WalletStore` in schema version 3, before queue deadlines.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV3 {
+    pub settings: SettingsV1,
+    pub queue_nonce: u64,
+}
+
+/// Layout of `WalletStore` in schema version 2, before queue nonces.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV2 {
+    pub settings: SettingsV1,
+}
+
+/// Layout of `WalletStore` up to schema version 1, when expiry users and the
+/// call queue were kept on the heap and serialized on every upgrade.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WalletStoreV1<TCycles> {
+    pub expiry_users: BTreeMap<Principal, ExpiryUser>,
+    pub settings: SettingsV1,
     pub call_queue: BTreeMap<String, MethodQueueItem<TCycles>>,
 }
 
-#[derive(CandidType, Clone)]
+#[derive(CandidType, Deserialize, Clone)]
 pub struct MethodQueueItem<TCycles> {
     pub hash: String,
     pub user: Principal,
     pub time_stamp: u64,
+    /// Nonce the hash was derived with, `None` for items queued before nonces existed.
+    pub nonce: Option<u64>,
     pub payload: CallCanisterArgs<TCycles>,
     pub owner_reply: OwnerReply,
+    /// `None` for items queued before statuses existed, see `status`.
+    pub status: Option<QueueStatus>,
+    /// Time after which the call can no longer be approved. `None` for items
+    /// queued before deadlines existed, see `deadline`.
+    pub expires_at: Option<u64>,
+    /// Time of the last status change.
+    pub updated_at: Option<u64>,
+    /// Approvals needed, fixed when the call is queued. `None` uses
+    /// `Settings::approval_threshold`.
+    pub threshold: Option<u32>,
+    pub votes: Option<Vec<Vote>>,
+    /// Calls of a batch queued as one unit; `payload` is the first of them.
+    pub batch: Option<Vec<BatchCall>>,
+    /// Replies of the batch calls that ran once the batch was approved.
+    pub batch_results: Option<Vec<CallResult>>,
+    /// How `payload`, or each call of `batch`, was registered when the call
+    /// was queued. `None` for items queued before method types were kept.
+    pub method_types: Option<Vec<MethodType>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq)]
+pub struct Vote {
+    pub approver: Principal,
+    pub approve: bool,
+    pub time: u64,
+}
+
+impl<TCycles> MethodQueueItem<TCycles> {
+    pub fn status(&self) -> QueueStatus {
+        self.status.unwrap_or(match &self.owner_reply {
+            OwnerReply::NotFound | OwnerReply::Pending { .. } => QueueStatus::Pending,
+            OwnerReply::Approved(Ok(_)) => QueueStatus::Approved,
+            OwnerReply::Approved(Err(_)) => QueueStatus::Failed,
+            OwnerReply::Rejected(_) => QueueStatus::Rejected,
+        })
+    }
+
+    /// Time after which the call can no longer be approved. Items queued
+    /// before deadlines existed get the default approval period, counted from
+    /// when they were queued.
+    pub fn deadline(&self) -> u64 {
+        self.expires_at
+            .unwrap_or_else(|| self.time_stamp.saturating_add(QUEUE_APPROVAL_PERIOD))
+    }
+
+    pub fn is_past_deadline(&self, now: u64) -> bool {
+        now >= self.deadline()
+    }
+
+    /// How the `index`th call was registered when it was queued.
+    pub fn method_type(&self, index: usize) -> Option<MethodType> {
+        self.method_types.as_ref()?.get(index).cloned()
+    }
+}
+
+/// Where a queued call is in its life. A call only leaves `Pending` once:
+///
+/// ```text
+/// Pending -> Executing -> Approved | Failed
+/// Pending -> Rejected
+/// Pending -> Expired
+/// ```
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueStatus {
+    Pending,
+    /// Approved by the owner, the call to the target is in flight.
+    Executing,
+    /// The target replied.
+    Approved,
+    /// The target rejected the call, or it was still executing an hour
+    /// after it was approved.
+    Failed,
+    Rejected,
+    Expired,
+}
+
+/// Versioned layout of `WalletStore` as written to stable memory on upgrade.
+///
+/// Every variant is a schema version. When the shape of `WalletStore`
+/// changes, freeze the previous shape in a new `WalletStoreV<n>` type, add the
+/// current one as `V<n+1>` and extend `service::migrate` so wallets saved by
+/// any older release still restore. Entries of the stable maps are Candid
+/// encoded one by one, so new `opt` fields on `ExpiryUser` or
+/// `MethodQueueItem` decode as `None` from older entries without a migration.
+#[derive(CandidType, Deserialize)]
+pub enum StableWalletStore {
+    V1(WalletStoreV1<u128>),
+    V2(WalletStoreV2),
+    V3(WalletStoreV3),
+    V4(WalletStoreV4),
+    V5(WalletStoreV5),
+    V6(WalletStoreV6),
+    V7(WalletStoreV7),
+    V8(WalletStoreV8),
+    V9(WalletStoreV9),
+    V10(WalletStore),
+}
+
+/// Stable map key for a principal.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PrincipalKey(pub Principal);
+
+impl Storable for PrincipalKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        PrincipalKey(Principal::from_slice(bytes.as_ref()))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 29,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for ExpiryUser {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for MethodQueueItem<u128> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Selects queued calls in `list_queue`. Unset fields match every call.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct QueueFilter {
+    pub user: Option<Principal>,
+    pub canister: Option<Principal>,
+    pub method_name: Option<String>,
+    pub status: Option<QueueStatus>,
+    /// Queued at or after this time.
+    pub from: Option<u64>,
+    /// Queued before this time.
+    pub to: Option<u64>,
+}
+
+impl QueueFilter {
+    pub fn matches<TCycles>(&self, item: &MethodQueueItem<TCycles>) -> bool {
+        self.user.is_none_or(|u| u == item.user)
+            && self.canister.is_none_or(|c| c == item.payload.canister)
+            && self
+                .method_name
+                .as_ref()
+                .is_none_or(|m| *m == item.payload.method_name)
+            && self.status.is_none_or(|s| s == item.status())
+            && self.from.is_none_or(|t| item.time_stamp >= t)
+            && self.to.is_none_or(|t| item.time_stamp < t)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct QueueItemView {
+    pub item: MethodQueueItem<u128>,
+    pub status: QueueStatus,
+    /// Arguments in Candid text format, when the method's `ArgPolicy` has a signature.
+    pub decoded_args: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct QueuePage {
+    pub items: Vec<QueueItemView>,
+    /// Pass as `cursor` to get the next page, `None` on the last page. A page
+    /// that stopped looking before finding `limit` calls has one too.
+    pub next_cursor: Option<String>,
+}
+
+/// One entry of the audit log, written for every call the wallet forwards.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub id: u64,
+    /// Who the call was made for: the delegate, or the owner calling directly.
+    pub caller: Principal,
+    pub canister: Principal,
+    pub method_name: String,
+    /// SHA-256 of the call arguments, hex encoded.
+    pub arg_hash: String,
+    pub cycles: u128,
+    pub cycles_refunded: u128,
+    pub outcome: AuditOutcome,
+    pub time: u64,
+    /// Queue id of a call that ran after approval.
+    pub queue_id: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq)]
+pub enum AuditOutcome {
+    Replied,
+    /// A one-way call was sent; what the target did with it is not known.
+    Accepted,
+    Rejected {
+        reject_code: u8,
+        message: String,
+    },
+    /// An approved call finished after the sweep had already failed it, see
+    /// `QUEUE_EXECUTION_TIMEOUT`. `error` is set when the call failed too.
+    LateReply {
+        error: Option<String>,
+    },
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Selects entries in `list_audit_log`. Unset fields match every entry.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AuditFilter {
+    pub caller: Option<Principal>,
+    pub canister: Option<Principal>,
+    pub method_name: Option<String>,
+    /// Forwarded at or after this time.
+    pub from: Option<u64>,
+    /// Forwarded before this time.
+    pub to: Option<u64>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.caller.is_none_or(|c| c == entry.caller)
+            && self.canister.is_none_or(|c| c == entry.canister)
+            && self
+                .method_name
+                .as_ref()
+                .is_none_or(|m| *m == entry.method_name)
+            && self.from.is_none_or(|t| entry.time >= t)
+            && self.to.is_none_or(|t| entry.time < t)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `cursor` to get the next page, `None` on the last page. A page
+    /// that stopped looking before finding `limit` entries has one too.
+    pub next_cursor: Option<u64>,
+}
+
+/// Query result a client can verify: each of `values` is SHA-256 hashed into a
+/// leaf of `witness`, whose root hash is certified by `certificate`.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CertifiedData {
+    /// Candid encoded values.
+    pub values: Vec<Vec<u8>>,
+    /// CBOR encoded hash tree, see the IC interface spec.
+    #[serde(with = "serde_bytes")]
+    pub witness: Vec<u8>,
+    /// `None` when not called as a query.
+    pub certificate: Option<Vec<u8>>,
 }
 
 #[derive(CandidType, Clone)]
@@ -87,11 +1052,84 @@ pub struct Settings {
     pub expiry_period: u64,
     pub proxy_black_list: BTreeMap<Principal, String>,
     pub method_valid_type: MethodValidationType,
+    /// How long a queued call waits for approval before it expires.
+    pub queue_approval_period: u64,
+    /// How long a finished queued call is kept before it is pruned.
+    pub queue_retention_period: u64,
+    /// Principals that vote on queued calls. While empty, any owner decides alone.
+    pub approvers: BTreeSet<Principal>,
+    pub approval_threshold: u32,
+    /// Threshold for key operations, `None` uses `approval_threshold`.
+    pub key_operation_threshold: Option<u32>,
+    /// Limits the calls of all delegates together.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Settings {
+    /// Approvals that execute a queued call and vetoes that reject it, which is
+    /// as soon as the remaining approvers can no longer reach the threshold.
+    pub fn approval_rule(&self, threshold: Option<u32>) -> (u32, u32) {
+        let voters = self.approvers.len() as u32;
+        if voters == 0 {
+            return (1, 1);
+        }
+        let required = threshold
+            .unwrap_or(self.approval_threshold)
+            .clamp(1, voters);
+        (required, voters - required + 1)
+    }
+
+    /// Approvals and vetoes among `votes`. Votes of principals removed from
+    /// the approvers since no longer count.
+    pub fn count_votes(&self, votes: &[Vote]) -> (u32, u32) {
+        let counted = votes
+            .iter()
+            .filter(|v| self.approvers.is_empty() || self.approvers.contains(&v.approver));
+        let (approvals, vetoes): (Vec<_>, Vec<_>) = counted.partition(|v| v.approve);
+        (approvals.len() as u32, vetoes.len() as u32)
+    }
+}
+
+/// Layout of `Settings` in schema versions 5 and 6, before rate limits.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct SettingsV3 {
+    pub expiry_period: u64,
+    pub proxy_black_list: BTreeMap<Principal, String>,
+    pub method_valid_type: MethodValidationType,
+    pub queue_approval_period: u64,
+    pub queue_retention_period: u64,
+    pub approvers: BTreeSet<Principal>,
+    pub approval_threshold: u32,
+    pub key_operation_threshold: Option<u32>,
+}
+
+/// Layout of `Settings` in schema version 4, before approvers.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct SettingsV2 {
+    pub expiry_period: u64,
+    pub proxy_black_list: BTreeMap<Principal, String>,
+    pub method_valid_type: MethodValidationType,
+    pub queue_approval_period: u64,
+    pub queue_retention_period: u64,
+}
+
+/// Layout of `Settings` up to schema version 3, before queue periods.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct SettingsV1 {
+    pub expiry_period: u64,
+    pub proxy_black_list: BTreeMap<Principal, String>,
+    pub method_valid_type: MethodValidationType,
 }
 
 #[derive(CandidType, Deserialize, Clone, PartialEq)]
 pub enum OwnerReply {
     NotFound,
+    /// The vote was recorded but the call has neither enough approvals to run
+    /// nor enough vetoes to be rejected yet.
+    Pending {
+        approvals: u32,
+        threshold: u32,
+    },
     Approved(Result<CallResult, String>),
     Rejected(String),
 }
@@ -1,23 +1,122 @@
 import type { Principal } from '@dfinity/principal';
 import type { ActorMethod } from '@dfinity/agent';
 
+export interface Account {
+  'owner' : Principal,
+  'subaccount' : [] | [Array<number>],
+}
+export interface Approval {
+  'ledger' : Principal,
+  'from_subaccount' : [] | [Array<number>],
+  'spender' : Account,
+  'amount' : bigint,
+  'expires_at' : [] | [bigint],
+  'approved_by' : Principal,
+  'time' : bigint,
+}
+export interface ApprovalRevocation {
+  'approval' : Approval,
+  'error' : [] | [string],
+}
+export interface ArgConstraint {
+  'arg' : number,
+  'path' : Array<string>,
+  'rule' : ArgRule,
+}
+export interface ArgPolicy {
+  'constraints' : Array<ArgConstraint>,
+  'signature' : [] | [string],
+}
+export type ArgRule = { 'PrincipalIn' : Array<Principal> } |
+  { 'TextIn' : Array<string> } |
+  { 'NatMax' : bigint } |
+  { 'PrincipalEq' : Principal };
+export interface AuditEntry {
+  'id' : bigint,
+  'cycles_refunded' : bigint,
+  'queue_id' : [] | [string],
+  'time' : bigint,
+  'arg_hash' : string,
+  'cycles' : bigint,
+  'method_name' : string,
+  'canister' : Principal,
+  'caller' : Principal,
+  'outcome' : AuditOutcome,
+}
+export interface AuditFilter {
+  'to' : [] | [bigint],
+  'from' : [] | [bigint],
+  'method_name' : [] | [string],
+  'canister' : [] | [Principal],
+  'caller' : [] | [Principal],
+}
+export type AuditOutcome = { 'Replied' : null } |
+  { 'Accepted' : null } |
+  { 'Rejected' : { 'reject_code' : number, 'message' : string } } |
+  { 'LateReply' : { 'error' : [] | [string] } };
+export interface AuditPage {
+  'entries' : Array<AuditEntry>,
+  'next_cursor' : [] | [bigint],
+}
+export interface BalanceResult { 'amount' : bigint }
+export interface BalanceResult_1 { 'amount' : bigint }
+export interface BatchCall {
+  'args_from' : [] | [number],
+  'call' : CallCanisterArgs,
+}
+export type BatchMode = { 'Sequential' : null } |
+  { 'AllOrNothing' : null };
+export interface BatchResult {
+  'stopped_at' : [] | [number],
+  'results' : Array<ProxyCallResult>,
+}
 export interface CallCanisterArgs {
   'args' : Array<number>,
   'cycles' : bigint,
   'method_name' : string,
   'canister' : Principal,
 }
+export interface CallCanisterArgs_1 {
+  'args' : Array<number>,
+  'cycles' : bigint,
+  'method_name' : string,
+  'canister' : Principal,
+}
 export interface CallResult { 'return' : Array<number> }
+export interface CanisterSettings {
+  'controller' : [] | [Principal],
+  'controllers' : [] | [Array<Principal>],
+  'compute_allocation' : [] | [bigint],
+  'memory_allocation' : [] | [bigint],
+  'freezing_threshold' : [] | [bigint],
+}
+export interface CreateCanisterArgs {
+  'cycles' : bigint,
+  'settings' : CanisterSettings,
+}
+export interface CreateResult { 'canister_id' : Principal }
+export interface CertifiedData {
+  'certificate' : [] | [Array<number>],
+  'values' : Array<Array<number>>,
+  'witness' : Array<number>,
+}
+export interface CyclesBudget { 'total' : bigint, 'per_call' : [] | [bigint] }
 export interface ExpiryUser {
   'user' : Principal,
   'expiry_timestamp' : bigint,
   'timestamp' : bigint,
+  'cycles' : [] | [CyclesBudget],
   'target_list' : Array<ProxyActorItem>,
+  'rate_limit' : [] | [RateLimit],
 }
 export interface Method {
+  'approval_threshold' : [] | [number],
   'name' : string,
   'method_type' : MethodType,
+  'cycles' : [] | [CyclesBudget],
+  'arg_policy' : [] | [ArgPolicy],
   'key_operation' : boolean,
+  'rate_limit' : [] | [RateLimit],
 }
 export type MethodType = { 'CALL' : null } |
   { 'OneWay' : null } |
@@ -28,15 +127,118 @@ export type MethodValidationType = { 'ALL' : null } |
   { 'UPDATE' : null };
 export type OwnerReply = { 'Approved' : Result_3 } |
   { 'NotFound' : null } |
-  { 'Rejected' : string };
+  { 'Rejected' : string } |
+  { 'Pending' : { 'threshold' : number, 'approvals' : number } };
+export interface MethodQueueItem {
+  'status' : [] | [QueueStatus],
+  'updated_at' : [] | [bigint],
+  'threshold' : [] | [number],
+  'votes' : [] | [Array<Vote>],
+  'hash' : string,
+  'user' : Principal,
+  'nonce' : [] | [bigint],
+  'expires_at' : [] | [bigint],
+  'owner_reply' : OwnerReply,
+  'time_stamp' : bigint,
+  'payload' : CallCanisterArgs,
+  'batch' : [] | [Array<BatchCall>],
+  'batch_results' : [] | [Array<CallResult>],
+  'method_types' : [] | [Array<MethodType>],
+}
+export interface TokenAllowance {
+  'ledger' : Principal,
+  'max_per_transfer' : [] | [bigint],
+  'spent_today' : bigint,
+  'remaining_today' : [] | [bigint],
+}
+export interface TokenPolicy {
+  'max_per_transfer' : [] | [bigint],
+  'max_per_day' : [] | [bigint],
+  'recipients' : [] | [Array<Account>],
+  'fee' : bigint,
+  'max_approval' : [] | [bigint],
+  'spenders' : [] | [Array<Account>],
+}
+export interface Vote {
+  'time' : bigint,
+  'approve' : boolean,
+  'approver' : Principal,
+}
+export interface QueueFilter {
+  'to' : [] | [bigint],
+  'status' : [] | [QueueStatus],
+  'from' : [] | [bigint],
+  'user' : [] | [Principal],
+  'method_name' : [] | [string],
+  'canister' : [] | [Principal],
+}
+export interface QueueItemView {
+  'status' : QueueStatus,
+  'decoded_args' : [] | [string],
+  'item' : MethodQueueItem,
+}
+export interface QueuePage {
+  'next_cursor' : [] | [string],
+  'items' : Array<QueueItemView>,
+}
+export interface NftPolicy {
+  'recipients' : [] | [Array<Account>],
+  'requires_approval' : boolean,
+  'max_transfers' : [] | [number],
+  'token_ids' : [] | [Array<string>],
+}
 export interface ProxyActorItem {
   'methods' : Array<[string, Method]>,
   'canister' : Principal,
+  'rate_limit' : [] | [RateLimit],
+  'deny' : [] | [Array<string>],
+  'all_queries' : [] | [boolean],
+  'token_policy' : [] | [TokenPolicy],
+  'nft_policy' : [] | [NftPolicy],
 }
 export interface ProxyActorTargets {
   'targets' : Array<ProxyActorItem>,
+  'cycles' : [] | [CyclesBudget],
   'expiration' : [] | [bigint],
+  'rate_limit' : [] | [RateLimit],
 }
+export interface RateCounter {
+  'key' : RateKey,
+  'last_minute' : number,
+  'last_hour' : number,
+  'last_day' : number,
+}
+export type RateKey = { 'Wallet' : null } |
+  { 'Delegate' : Principal } |
+  { 'Target' : [Principal, Principal] } |
+  { 'Method' : [Principal, Principal, string] };
+export interface RateLimit {
+  'per_minute' : [] | [number],
+  'per_hour' : [] | [number],
+  'per_day' : [] | [number],
+}
+export type Decision = { 'Deny' : Denial } |
+  { 'Execute' : null } |
+  { 'Queue' : null };
+export type Denial = { 'MethodNotAllowed' : null } |
+  { 'NotDelegate' : null } |
+  { 'ArgumentRejected' : { 'reason' : string } } |
+  { 'CanisterNotAllowed' : null } |
+  { 'CanisterBlacklisted' : null } |
+  { 'SessionExpired' : { 'expired_at' : bigint } } |
+  { 'BudgetExceeded' : { 'requested' : bigint, 'available' : bigint } } |
+  { 'NotAQuery' : null } |
+  { 'ApprovalRequired' : null } |
+  { 'RateLimited' : { 'limit' : number, 'window' : bigint, 'retry_at' : bigint } } |
+  { 'TransferRejected' : { 'reason' : string } } |
+  { 'AllowanceExceeded' : { 'requested' : bigint, 'remaining' : bigint } };
+export type ProxyCallResult = {
+    'Queued' : { 'id' : string, 'expires_at' : [] | [bigint] }
+  } |
+  { 'Rejected' : { 'reject_code' : number, 'message' : string } } |
+  { 'Unauthorized' : { 'reason' : Denial } } |
+  { 'Executed' : CallResult } |
+  { 'Submitted' : { 'id' : bigint } };
 export interface QueueHash {
   'hash' : string,
   'user' : Principal,
@@ -50,11 +252,43 @@ export type Result_2 = { 'Ok' : Array<string> } |
   { 'Err' : string };
 export type Result_3 = { 'Ok' : CallResult } |
   { 'Err' : string };
+export type QueueStatus = { 'Failed' : null } |
+  { 'Executing' : null } |
+  { 'Approved' : null } |
+  { 'Rejected' : null } |
+  { 'Expired' : null } |
+  { 'Pending' : null };
 export type Result_4 = { 'Ok' : boolean } |
   { 'Err' : string };
+export type Result_5 = { 'Ok' : OwnerReply } |
+  { 'Err' : string };
+export type Result_6 = { 'Ok' : ScheduledCall } |
+  { 'Err' : string };
+export type Result_7 = { 'Ok' : [] | [ScheduledCall] } |
+  { 'Err' : string };
+export type Result_8 = { 'Ok' : BatchResult } |
+  { 'Err' : string };
+export type Result_9 = { 'Ok' : string } |
+  { 'Err' : string };
+export type Result_10 = { 'Ok' : CreateResult } |
+  { 'Err' : string };
+export interface ReceiveOptions { 'memo' : [] | [string] }
+export interface SendCyclesArgs { 'canister' : Principal, 'amount' : bigint }
+export interface ScheduledCall {
+  'id' : bigint,
+  'next_run' : [] | [bigint],
+  'interval' : [] | [bigint],
+  'runs' : bigint,
+  'user' : Principal,
+  'last_result' : [] | [ProxyCallResult],
+  'last_run' : [] | [bigint],
+  'payload' : CallCanisterArgs,
+}
 export interface _SERVICE {
   'add_expiry_user' : ActorMethod<[Principal, ProxyActorTargets], ExpiryUser>,
   'add_proxy_black_list' : ActorMethod<[Principal], string>,
+  'cancel_scheduled_call' : ActorMethod<[bigint], Result_7>,
+  'check_call' : ActorMethod<[CallCanisterArgs], Decision>,
   'balance_get' : ActorMethod<[], Result>,
   'ego_canister_add' : ActorMethod<[string, Principal], Result_1>,
   'ego_controller_add' : ActorMethod<[Principal], Result_1>,
@@ -68,14 +302,47 @@ export interface _SERVICE {
   'ego_user_add' : ActorMethod<[Principal], Result_1>,
   'ego_user_remove' : ActorMethod<[Principal], Result_1>,
   'ego_user_set' : ActorMethod<[Array<Principal>], Result_1>,
+  'extend_expiry_user' : ActorMethod<[Principal, bigint], [] | [ExpiryUser]>,
+  'get_account_identifier' : ActorMethod<[[] | [Array<number>]], Result_9>,
+  'get_certified_audit_log' : ActorMethod<[bigint, number], CertifiedData>,
+  'get_certified_queue_method' : ActorMethod<[string], CertifiedData>,
+  'get_expiry_user' : ActorMethod<[Principal], [] | [ExpiryUser]>,
   'get_queue_reply' : ActorMethod<[string], [] | [OwnerReply]>,
+  'get_queue_status' : ActorMethod<[string], [] | [QueueStatus]>,
   'get_queue_unconfirmed' : ActorMethod<[Principal], Array<QueueHash>>,
+  'get_rate_counters' : ActorMethod<[], Array<RateCounter>>,
+  'get_token_allowance' : ActorMethod<[Principal], [] | [TokenAllowance]>,
   'has_queue_method' : ActorMethod<[string], boolean>,
   'is_proxy_black_list' : ActorMethod<[Principal], boolean>,
-  'owner_confirm' : ActorMethod<[string, boolean], OwnerReply>,
-  'proxy_call' : ActorMethod<[CallCanisterArgs], Result_3>,
+  'list_approvals' : ActorMethod<[], Array<Approval>>,
+  'list_audit_log' : ActorMethod<
+    [AuditFilter, [] | [bigint], number],
+    AuditPage
+  >,
+  'list_queue' : ActorMethod<[QueueFilter, [] | [string], number], QueuePage>,
+  'list_scheduled_calls' : ActorMethod<[], Array<ScheduledCall>>,
+  'owner_confirm' : ActorMethod<[string, boolean], Result_5>,
+  'proxy_call' : ActorMethod<[CallCanisterArgs], ProxyCallResult>,
+  'proxy_call_batch' : ActorMethod<[Array<BatchCall>, BatchMode], Result_8>,
+  'proxy_query' : ActorMethod<[CallCanisterArgs], ProxyCallResult>,
   'remove_proxy_black_list' : ActorMethod<[Principal], [] | [string]>,
   'remove_queue_method' : ActorMethod<[string], Result_4>,
+  'revoke_approvals' : ActorMethod<[], Array<ApprovalRevocation>>,
+  'revoke_expiry_user' : ActorMethod<[Principal], [] | [ExpiryUser]>,
+  'schedule_call' : ActorMethod<[CallCanisterArgs, bigint, [] | [bigint]], Result_6>,
+  'set_approvers' : ActorMethod<[Array<Principal>, number], Result_1>,
   'set_expiry_period' : ActorMethod<[bigint], undefined>,
+  'set_key_operation_threshold' : ActorMethod<[[] | [number]], undefined>,
   'set_method_validate_type' : ActorMethod<[MethodValidationType], undefined>,
+  'set_queue_approval_period' : ActorMethod<[bigint], undefined>,
+  'set_queue_retention_period' : ActorMethod<[bigint], undefined>,
+  'set_rate_limit' : ActorMethod<[[] | [RateLimit]], undefined>,
+  'shorten_expiry_user' : ActorMethod<[Principal, bigint], [] | [ExpiryUser]>,
+  'wallet_balance' : ActorMethod<[], BalanceResult>,
+  'wallet_balance128' : ActorMethod<[], BalanceResult_1>,
+  'wallet_call' : ActorMethod<[CallCanisterArgs_1], Result_3>,
+  'wallet_call128' : ActorMethod<[CallCanisterArgs], Result_3>,
+  'wallet_create_canister' : ActorMethod<[CreateCanisterArgs], Result_10>,
+  'wallet_receive' : ActorMethod<[[] | [ReceiveOptions]], undefined>,
+  'wallet_send' : ActorMethod<[SendCyclesArgs], Result_1>,
 }
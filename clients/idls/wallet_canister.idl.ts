@@ -5,24 +5,97 @@ export const idlFactory = ({ IDL }) => {
     'CompositeQuery' : IDL.Null,
     'QUERY' : IDL.Null,
   });
+  const CertifiedData = IDL.Record({
+    'certificate' : IDL.Opt(IDL.Vec(IDL.Nat8)),
+    'values' : IDL.Vec(IDL.Vec(IDL.Nat8)),
+    'witness' : IDL.Vec(IDL.Nat8),
+  });
+  const CyclesBudget = IDL.Record({
+    'total' : IDL.Nat,
+    'per_call' : IDL.Opt(IDL.Nat),
+  });
+  const RateLimit = IDL.Record({
+    'per_minute' : IDL.Opt(IDL.Nat32),
+    'per_hour' : IDL.Opt(IDL.Nat32),
+    'per_day' : IDL.Opt(IDL.Nat32),
+  });
+  const Account = IDL.Record({
+    'owner' : IDL.Principal,
+    'subaccount' : IDL.Opt(IDL.Vec(IDL.Nat8)),
+  });
+  const TokenPolicy = IDL.Record({
+    'max_per_transfer' : IDL.Opt(IDL.Nat),
+    'max_per_day' : IDL.Opt(IDL.Nat),
+    'recipients' : IDL.Opt(IDL.Vec(Account)),
+    'fee' : IDL.Nat,
+    'max_approval' : IDL.Opt(IDL.Nat),
+    'spenders' : IDL.Opt(IDL.Vec(Account)),
+  });
+  const Approval = IDL.Record({
+    'ledger' : IDL.Principal,
+    'from_subaccount' : IDL.Opt(IDL.Vec(IDL.Nat8)),
+    'spender' : Account,
+    'amount' : IDL.Nat,
+    'expires_at' : IDL.Opt(IDL.Nat64),
+    'approved_by' : IDL.Principal,
+    'time' : IDL.Nat64,
+  });
+  const ApprovalRevocation = IDL.Record({
+    'approval' : Approval,
+    'error' : IDL.Opt(IDL.Text),
+  });
+  const ArgRule = IDL.Variant({
+    'PrincipalIn' : IDL.Vec(IDL.Principal),
+    'TextIn' : IDL.Vec(IDL.Text),
+    'NatMax' : IDL.Nat,
+    'PrincipalEq' : IDL.Principal,
+  });
+  const ArgConstraint = IDL.Record({
+    'arg' : IDL.Nat32,
+    'path' : IDL.Vec(IDL.Text),
+    'rule' : ArgRule,
+  });
+  const ArgPolicy = IDL.Record({
+    'constraints' : IDL.Vec(ArgConstraint),
+    'signature' : IDL.Opt(IDL.Text),
+  });
   const Method = IDL.Record({
+    'approval_threshold' : IDL.Opt(IDL.Nat32),
     'name' : IDL.Text,
     'method_type' : MethodType,
+    'cycles' : IDL.Opt(CyclesBudget),
+    'arg_policy' : IDL.Opt(ArgPolicy),
     'key_operation' : IDL.Bool,
+    'rate_limit' : IDL.Opt(RateLimit),
+  });
+  const NftPolicy = IDL.Record({
+    'recipients' : IDL.Opt(IDL.Vec(Account)),
+    'requires_approval' : IDL.Bool,
+    'max_transfers' : IDL.Opt(IDL.Nat32),
+    'token_ids' : IDL.Opt(IDL.Vec(IDL.Text)),
   });
   const ProxyActorItem = IDL.Record({
     'methods' : IDL.Vec(IDL.Tuple(IDL.Text, Method)),
     'canister' : IDL.Principal,
+    'rate_limit' : IDL.Opt(RateLimit),
+    'deny' : IDL.Opt(IDL.Vec(IDL.Text)),
+    'all_queries' : IDL.Opt(IDL.Bool),
+    'token_policy' : IDL.Opt(TokenPolicy),
+    'nft_policy' : IDL.Opt(NftPolicy),
   });
   const ProxyActorTargets = IDL.Record({
     'targets' : IDL.Vec(ProxyActorItem),
+    'cycles' : IDL.Opt(CyclesBudget),
     'expiration' : IDL.Opt(IDL.Nat64),
+    'rate_limit' : IDL.Opt(RateLimit),
   });
   const ExpiryUser = IDL.Record({
     'user' : IDL.Principal,
     'expiry_timestamp' : IDL.Nat64,
     'timestamp' : IDL.Nat64,
+    'cycles' : IDL.Opt(CyclesBudget),
     'target_list' : IDL.Vec(ProxyActorItem),
+    'rate_limit' : IDL.Opt(RateLimit),
   });
   const Result = IDL.Variant({ 'Ok' : IDL.Nat, 'Err' : IDL.Text });
   const Result_1 = IDL.Variant({ 'Ok' : IDL.Null, 'Err' : IDL.Text });
@@ -33,6 +106,7 @@ export const idlFactory = ({ IDL }) => {
     'Approved' : Result_3,
     'NotFound' : IDL.Null,
     'Rejected' : IDL.Text,
+    'Pending' : IDL.Record({ 'threshold' : IDL.Nat32, 'approvals' : IDL.Nat32 }),
   });
   const QueueHash = IDL.Record({
     'hash' : IDL.Text,
@@ -45,12 +119,200 @@ export const idlFactory = ({ IDL }) => {
     'method_name' : IDL.Text,
     'canister' : IDL.Principal,
   });
+  const Denial = IDL.Variant({
+    'MethodNotAllowed' : IDL.Null,
+    'NotDelegate' : IDL.Null,
+    'ArgumentRejected' : IDL.Record({ 'reason' : IDL.Text }),
+    'CanisterNotAllowed' : IDL.Null,
+    'CanisterBlacklisted' : IDL.Null,
+    'SessionExpired' : IDL.Record({ 'expired_at' : IDL.Nat64 }),
+    'BudgetExceeded' : IDL.Record({
+      'requested' : IDL.Nat,
+      'available' : IDL.Nat,
+    }),
+    'NotAQuery' : IDL.Null,
+    'ApprovalRequired' : IDL.Null,
+    'RateLimited' : IDL.Record({
+      'limit' : IDL.Nat32,
+      'window' : IDL.Nat64,
+      'retry_at' : IDL.Nat64,
+    }),
+    'TransferRejected' : IDL.Record({ 'reason' : IDL.Text }),
+    'AllowanceExceeded' : IDL.Record({
+      'requested' : IDL.Nat,
+      'remaining' : IDL.Nat,
+    }),
+  });
+  const TokenAllowance = IDL.Record({
+    'ledger' : IDL.Principal,
+    'max_per_transfer' : IDL.Opt(IDL.Nat),
+    'spent_today' : IDL.Nat,
+    'remaining_today' : IDL.Opt(IDL.Nat),
+  });
+  const RateKey = IDL.Variant({
+    'Wallet' : IDL.Null,
+    'Delegate' : IDL.Principal,
+    'Target' : IDL.Tuple(IDL.Principal, IDL.Principal),
+    'Method' : IDL.Tuple(IDL.Principal, IDL.Principal, IDL.Text),
+  });
+  const RateCounter = IDL.Record({
+    'key' : RateKey,
+    'last_minute' : IDL.Nat32,
+    'last_hour' : IDL.Nat32,
+    'last_day' : IDL.Nat32,
+  });
+  const Decision = IDL.Variant({
+    'Deny' : Denial,
+    'Execute' : IDL.Null,
+    'Queue' : IDL.Null,
+  });
+  const ProxyCallResult = IDL.Variant({
+    'Queued' : IDL.Record({
+      'id' : IDL.Text,
+      'expires_at' : IDL.Opt(IDL.Nat64),
+    }),
+    'Rejected' : IDL.Record({ 'reject_code' : IDL.Nat8, 'message' : IDL.Text }),
+    'Unauthorized' : IDL.Record({ 'reason' : Denial }),
+    'Executed' : CallResult,
+    'Submitted' : IDL.Record({ 'id' : IDL.Nat64 }),
+  });
+  const BatchCall = IDL.Record({
+    'args_from' : IDL.Opt(IDL.Nat32),
+    'call' : CallCanisterArgs,
+  });
+  const BatchMode = IDL.Variant({
+    'Sequential' : IDL.Null,
+    'AllOrNothing' : IDL.Null,
+  });
+  const BatchResult = IDL.Record({
+    'stopped_at' : IDL.Opt(IDL.Nat32),
+    'results' : IDL.Vec(ProxyCallResult),
+  });
+  const Result_8 = IDL.Variant({ 'Ok' : BatchResult, 'Err' : IDL.Text });
+  const Result_9 = IDL.Variant({ 'Ok' : IDL.Text, 'Err' : IDL.Text });
+  const QueueStatus = IDL.Variant({
+    'Failed' : IDL.Null,
+    'Executing' : IDL.Null,
+    'Approved' : IDL.Null,
+    'Rejected' : IDL.Null,
+    'Expired' : IDL.Null,
+    'Pending' : IDL.Null,
+  });
   const Result_4 = IDL.Variant({ 'Ok' : IDL.Bool, 'Err' : IDL.Text });
+  const Result_5 = IDL.Variant({ 'Ok' : OwnerReply, 'Err' : IDL.Text });
+  const ScheduledCall = IDL.Record({
+    'id' : IDL.Nat64,
+    'next_run' : IDL.Opt(IDL.Nat64),
+    'interval' : IDL.Opt(IDL.Nat64),
+    'runs' : IDL.Nat64,
+    'user' : IDL.Principal,
+    'last_result' : IDL.Opt(ProxyCallResult),
+    'last_run' : IDL.Opt(IDL.Nat64),
+    'payload' : CallCanisterArgs,
+  });
+  const Result_6 = IDL.Variant({ 'Ok' : ScheduledCall, 'Err' : IDL.Text });
+  const Result_7 = IDL.Variant({
+    'Ok' : IDL.Opt(ScheduledCall),
+    'Err' : IDL.Text,
+  });
+  const AuditFilter = IDL.Record({
+    'to' : IDL.Opt(IDL.Nat64),
+    'from' : IDL.Opt(IDL.Nat64),
+    'method_name' : IDL.Opt(IDL.Text),
+    'canister' : IDL.Opt(IDL.Principal),
+    'caller' : IDL.Opt(IDL.Principal),
+  });
+  const AuditOutcome = IDL.Variant({
+    'Replied' : IDL.Null,
+    'Accepted' : IDL.Null,
+    'Rejected' : IDL.Record({ 'reject_code' : IDL.Nat8, 'message' : IDL.Text }),
+    'LateReply' : IDL.Record({ 'error' : IDL.Opt(IDL.Text) }),
+  });
+  const AuditEntry = IDL.Record({
+    'id' : IDL.Nat64,
+    'cycles_refunded' : IDL.Nat,
+    'queue_id' : IDL.Opt(IDL.Text),
+    'time' : IDL.Nat64,
+    'arg_hash' : IDL.Text,
+    'cycles' : IDL.Nat,
+    'method_name' : IDL.Text,
+    'canister' : IDL.Principal,
+    'caller' : IDL.Principal,
+    'outcome' : AuditOutcome,
+  });
+  const AuditPage = IDL.Record({
+    'entries' : IDL.Vec(AuditEntry),
+    'next_cursor' : IDL.Opt(IDL.Nat64),
+  });
+  const QueueFilter = IDL.Record({
+    'to' : IDL.Opt(IDL.Nat64),
+    'status' : IDL.Opt(QueueStatus),
+    'from' : IDL.Opt(IDL.Nat64),
+    'user' : IDL.Opt(IDL.Principal),
+    'method_name' : IDL.Opt(IDL.Text),
+    'canister' : IDL.Opt(IDL.Principal),
+  });
+  const Vote = IDL.Record({
+    'time' : IDL.Nat64,
+    'approve' : IDL.Bool,
+    'approver' : IDL.Principal,
+  });
+  const MethodQueueItem = IDL.Record({
+    'status' : IDL.Opt(QueueStatus),
+    'updated_at' : IDL.Opt(IDL.Nat64),
+    'threshold' : IDL.Opt(IDL.Nat32),
+    'votes' : IDL.Opt(IDL.Vec(Vote)),
+    'hash' : IDL.Text,
+    'user' : IDL.Principal,
+    'nonce' : IDL.Opt(IDL.Nat64),
+    'expires_at' : IDL.Opt(IDL.Nat64),
+    'owner_reply' : OwnerReply,
+    'time_stamp' : IDL.Nat64,
+    'payload' : CallCanisterArgs,
+    'batch' : IDL.Opt(IDL.Vec(BatchCall)),
+    'batch_results' : IDL.Opt(IDL.Vec(CallResult)),
+    'method_types' : IDL.Opt(IDL.Vec(MethodType)),
+  });
+  const QueueItemView = IDL.Record({
+    'status' : QueueStatus,
+    'decoded_args' : IDL.Opt(IDL.Text),
+    'item' : MethodQueueItem,
+  });
+  const QueuePage = IDL.Record({
+    'next_cursor' : IDL.Opt(IDL.Text),
+    'items' : IDL.Vec(QueueItemView),
+  });
   const MethodValidationType = IDL.Variant({
     'ALL' : IDL.Null,
     'KEY' : IDL.Null,
     'UPDATE' : IDL.Null,
   });
+  const BalanceResult = IDL.Record({ 'amount' : IDL.Nat64 });
+  const BalanceResult_1 = IDL.Record({ 'amount' : IDL.Nat });
+  const CallCanisterArgs_1 = IDL.Record({
+    'args' : IDL.Vec(IDL.Nat8),
+    'cycles' : IDL.Nat64,
+    'method_name' : IDL.Text,
+    'canister' : IDL.Principal,
+  });
+  const CanisterSettings = IDL.Record({
+    'controller' : IDL.Opt(IDL.Principal),
+    'controllers' : IDL.Opt(IDL.Vec(IDL.Principal)),
+    'compute_allocation' : IDL.Opt(IDL.Nat),
+    'memory_allocation' : IDL.Opt(IDL.Nat),
+    'freezing_threshold' : IDL.Opt(IDL.Nat),
+  });
+  const CreateCanisterArgs = IDL.Record({
+    'cycles' : IDL.Nat64,
+    'settings' : CanisterSettings,
+  });
+  const CreateResult = IDL.Record({ 'canister_id' : IDL.Principal });
+  const Result_10 = IDL.Variant({ 'Ok' : CreateResult, 'Err' : IDL.Text });
+  const ReceiveOptions = IDL.Record({ 'memo' : IDL.Opt(IDL.Text) });
+  const SendCyclesArgs = IDL.Record({
+    'canister' : IDL.Principal,
+    'amount' : IDL.Nat64,
+  });
   return IDL.Service({
     'add_expiry_user' : IDL.Func(
         [IDL.Principal, ProxyActorTargets],
@@ -58,6 +320,8 @@ export const idlFactory = ({ IDL }) => {
         [],
       ),
     'add_proxy_black_list' : IDL.Func([IDL.Principal], [IDL.Text], []),
+    'cancel_scheduled_call' : IDL.Func([IDL.Nat64], [Result_7], []),
+    'check_call' : IDL.Func([CallCanisterArgs], [Decision], ['query']),
     'balance_get' : IDL.Func([], [Result], ['query']),
     'ego_canister_add' : IDL.Func([IDL.Text, IDL.Principal], [Result_1], []),
     'ego_controller_add' : IDL.Func([IDL.Principal], [Result_1], []),
@@ -71,24 +335,98 @@ export const idlFactory = ({ IDL }) => {
     'ego_user_add' : IDL.Func([IDL.Principal], [Result_1], []),
     'ego_user_remove' : IDL.Func([IDL.Principal], [Result_1], []),
     'ego_user_set' : IDL.Func([IDL.Vec(IDL.Principal)], [Result_1], []),
+    'extend_expiry_user' : IDL.Func(
+        [IDL.Principal, IDL.Nat64],
+        [IDL.Opt(ExpiryUser)],
+        [],
+      ),
+    'get_account_identifier' : IDL.Func(
+        [IDL.Opt(IDL.Vec(IDL.Nat8))],
+        [Result_9],
+        ['query'],
+      ),
+    'get_certified_audit_log' : IDL.Func(
+        [IDL.Nat64, IDL.Nat32],
+        [CertifiedData],
+        ['query'],
+      ),
+    'get_certified_queue_method' : IDL.Func(
+        [IDL.Text],
+        [CertifiedData],
+        ['query'],
+      ),
+    'get_expiry_user' : IDL.Func(
+        [IDL.Principal],
+        [IDL.Opt(ExpiryUser)],
+        ['query'],
+      ),
     'get_queue_reply' : IDL.Func([IDL.Text], [IDL.Opt(OwnerReply)], ['query']),
+    'get_queue_status' : IDL.Func([IDL.Text], [IDL.Opt(QueueStatus)], ['query']),
     'get_queue_unconfirmed' : IDL.Func(
         [IDL.Principal],
         [IDL.Vec(QueueHash)],
         ['query'],
       ),
+    'get_rate_counters' : IDL.Func([], [IDL.Vec(RateCounter)], ['query']),
+    'get_token_allowance' : IDL.Func(
+        [IDL.Principal],
+        [IDL.Opt(TokenAllowance)],
+        ['query'],
+      ),
     'has_queue_method' : IDL.Func([IDL.Text], [IDL.Bool], ['query']),
     'is_proxy_black_list' : IDL.Func([IDL.Principal], [IDL.Bool], ['query']),
-    'owner_confirm' : IDL.Func([IDL.Text, IDL.Bool], [OwnerReply], []),
-    'proxy_call' : IDL.Func([CallCanisterArgs], [Result_3], []),
+    'list_approvals' : IDL.Func([], [IDL.Vec(Approval)], ['query']),
+    'list_audit_log' : IDL.Func(
+        [AuditFilter, IDL.Opt(IDL.Nat64), IDL.Nat32],
+        [AuditPage],
+        ['query'],
+      ),
+    'list_queue' : IDL.Func(
+        [QueueFilter, IDL.Opt(IDL.Text), IDL.Nat32],
+        [QueuePage],
+        ['query'],
+      ),
+    'list_scheduled_calls' : IDL.Func([], [IDL.Vec(ScheduledCall)], ['query']),
+    'owner_confirm' : IDL.Func([IDL.Text, IDL.Bool], [Result_5], []),
+    'proxy_call' : IDL.Func([CallCanisterArgs], [ProxyCallResult], []),
+    'proxy_call_batch' : IDL.Func(
+        [IDL.Vec(BatchCall), BatchMode],
+        [Result_8],
+        [],
+      ),
+    'proxy_query' : IDL.Func([CallCanisterArgs], [ProxyCallResult], ['query']),
     'remove_proxy_black_list' : IDL.Func(
         [IDL.Principal],
         [IDL.Opt(IDL.Text)],
         [],
       ),
     'remove_queue_method' : IDL.Func([IDL.Text], [Result_4], []),
+    'revoke_approvals' : IDL.Func([], [IDL.Vec(ApprovalRevocation)], []),
+    'revoke_expiry_user' : IDL.Func([IDL.Principal], [IDL.Opt(ExpiryUser)], []),
+    'schedule_call' : IDL.Func(
+        [CallCanisterArgs, IDL.Nat64, IDL.Opt(IDL.Nat64)],
+        [Result_6],
+        [],
+      ),
+    'set_approvers' : IDL.Func([IDL.Vec(IDL.Principal), IDL.Nat32], [Result_1], []),
     'set_expiry_period' : IDL.Func([IDL.Nat64], [], []),
+    'set_key_operation_threshold' : IDL.Func([IDL.Opt(IDL.Nat32)], [], []),
     'set_method_validate_type' : IDL.Func([MethodValidationType], [], []),
+    'set_queue_approval_period' : IDL.Func([IDL.Nat64], [], []),
+    'set_queue_retention_period' : IDL.Func([IDL.Nat64], [], []),
+    'set_rate_limit' : IDL.Func([IDL.Opt(RateLimit)], [], []),
+    'shorten_expiry_user' : IDL.Func(
+        [IDL.Principal, IDL.Nat64],
+        [IDL.Opt(ExpiryUser)],
+        [],
+      ),
+    'wallet_balance' : IDL.Func([], [BalanceResult], ['query']),
+    'wallet_balance128' : IDL.Func([], [BalanceResult_1], ['query']),
+    'wallet_call' : IDL.Func([CallCanisterArgs_1], [Result_3], []),
+    'wallet_call128' : IDL.Func([CallCanisterArgs], [Result_3], []),
+    'wallet_create_canister' : IDL.Func([CreateCanisterArgs], [Result_10], []),
+    'wallet_receive' : IDL.Func([IDL.Opt(ReceiveOptions)], [], []),
+    'wallet_send' : IDL.Func([SendCyclesArgs], [Result_1], []),
   });
 };
 export const init = ({ IDL }) => { return []; };
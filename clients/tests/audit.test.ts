@@ -0,0 +1,79 @@
+import { _SERVICE as walletService, AuditFilter, Method } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId, hasOwnProperty } from '@ego-js/utils';
+
+import { Principal } from '@dfinity/principal';
+import { addDelegate, callArgs } from './proxyActor';
+
+const noFilter: AuditFilter = { to: [], from: [], method_name: [], canister: [], caller: [] };
+
+describe('audit', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('forwarded calls are logged', async () => {
+    const owner = await ownerActor;
+    const { delegate, delegateWallet } = await addDelegate();
+    const result = await delegateWallet.proxy_call(callArgs('test_call'));
+    expect(hasOwnProperty(result, 'Executed')).toBe(true);
+
+    const page = await owner.list_audit_log({ ...noFilter, caller: [delegate.getPrincipal()] }, [], 100);
+    expect(page.entries.length).toBe(1);
+    expect(page.entries[0].method_name).toBe('test_call');
+    expect(page.entries[0].canister.toText()).toBe(targetCanisterId);
+    expect(page.entries[0].outcome).toEqual({ Replied: null });
+  });
+
+  test('denied calls are not logged', async () => {
+    const owner = await ownerActor;
+    const { delegate, delegateWallet } = await addDelegate();
+    await delegateWallet.proxy_call(callArgs('not_a_method'));
+
+    const page = await owner.list_audit_log({ ...noFilter, caller: [delegate.getPrincipal()] }, [], 100);
+    expect(page.entries).toEqual([]);
+  });
+
+  test('the log pages by id', async () => {
+    const owner = await ownerActor;
+    const { delegate, delegateWallet } = await addDelegate();
+    await delegateWallet.proxy_call(callArgs('test_call'));
+    await delegateWallet.proxy_call(callArgs('test_query'));
+
+    const filter: AuditFilter = { ...noFilter, caller: [delegate.getPrincipal()] };
+    const first = await owner.list_audit_log(filter, [], 1);
+    expect(first.entries.length).toBe(1);
+    const second = await owner.list_audit_log(filter, first.next_cursor, 1);
+    expect(second.entries.map(e => e.method_name)).toEqual(['test_query']);
+    expect(second.next_cursor).toEqual([]);
+  });
+
+  test('one-way calls are submitted and logged as accepted', async () => {
+    const owner = await ownerActor;
+    const { delegate, delegateWallet } = await addDelegate({
+      edit: target => {
+        const methods = target.methods as Array<[string, Method]>;
+        methods.find(([name]) => name === 'test_call')![1].method_type = { OneWay: null };
+      },
+    });
+
+    const result = await delegateWallet.proxy_call(callArgs('test_call'));
+    if (!hasOwnProperty(result, 'Submitted')) {
+      throw new Error('call was not submitted');
+    }
+    const page = await owner.list_audit_log({ ...noFilter, caller: [delegate.getPrincipal()] }, [], 100);
+    expect(page.entries.map(e => [e.id, e.outcome])).toEqual([[result.Submitted.id, { Accepted: null }]]);
+  });
+
+  test('only the owner reads the log', async () => {
+    const { delegateWallet } = await addDelegate();
+    await expect(delegateWallet.list_audit_log(noFilter, [], 100)).rejects.toBeTruthy();
+  });
+});
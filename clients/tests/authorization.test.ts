@@ -0,0 +1,119 @@
+import { _SERVICE as walletService } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId } from '@ego-js/utils';
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { Principal } from '@dfinity/principal';
+import { addDelegate, callArgs } from './proxyActor';
+
+describe('authorization', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('owner calls are executed', async () => {
+    const decision = await (await ownerActor).check_call(callArgs('test_call'));
+    expect(decision).toEqual({ Execute: null });
+  });
+
+  test('unknown callers are not delegates', async () => {
+    const stranger = await getActor<walletService>(Ed25519KeyIdentity.generate(), walletIDL, walletCanisterId);
+    expect(await stranger.check_call(callArgs('test_query'))).toEqual({ Deny: { NotDelegate: null } });
+
+    const result = await stranger.proxy_call(callArgs('test_query'));
+    expect(result).toEqual({ Unauthorized: { reason: { NotDelegate: null } } });
+  });
+
+  test('delegates are limited to their targets', async () => {
+    const { delegateWallet } = await addDelegate();
+    expect(await delegateWallet.check_call(callArgs('test_query'))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(callArgs('not_a_method'))).toEqual({
+      Deny: { MethodNotAllowed: null },
+    });
+    const otherCanister = { ...callArgs('test_query'), canister: Principal.fromText(walletCanisterId) };
+    expect(await delegateWallet.check_call(otherCanister)).toEqual({ Deny: { CanisterNotAllowed: null } });
+  });
+
+  test('blacklisted canisters are denied', async () => {
+    const { delegateWallet } = await addDelegate();
+    const owner = await ownerActor;
+    await owner.add_proxy_black_list(Principal.fromText(targetCanisterId));
+    try {
+      expect(await delegateWallet.check_call(callArgs('test_query'))).toEqual({
+        Deny: { CanisterBlacklisted: null },
+      });
+    } finally {
+      await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    }
+  });
+
+  test('cycles without a budget are denied', async () => {
+    const { delegateWallet } = await addDelegate();
+    expect(await delegateWallet.check_call(callArgs('test_query', BigInt(1000)))).toEqual({
+      Deny: { BudgetExceeded: { requested: BigInt(1000), available: BigInt(0) } },
+    });
+  });
+
+  test('proxy_query only forwards queries', async () => {
+    const { delegateWallet } = await addDelegate();
+    expect('Executed' in (await delegateWallet.proxy_query(callArgs('test_query')))).toBe(true);
+
+    expect(await delegateWallet.proxy_query(callArgs('test_call'))).toEqual({
+      Unauthorized: { reason: { NotAQuery: null } },
+    });
+    expect(await delegateWallet.proxy_query(callArgs('test_query', BigInt(1)))).toEqual({
+      Unauthorized: { reason: { BudgetExceeded: { requested: BigInt(1), available: BigInt(0) } } },
+    });
+  });
+
+  test('method patterns allow every matching method', async () => {
+    const { delegateWallet } = await addDelegate({
+      edit: target => {
+        const [, method] = target.methods.find(([name]) => name === 'test_call')!;
+        target.methods = [['test_call*', { ...method, name: 'test_call*' }]];
+      },
+    });
+    expect(await delegateWallet.check_call(callArgs('test_call'))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(callArgs('test_call_key'))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(callArgs('test_query'))).toEqual({
+      Deny: { MethodNotAllowed: null },
+    });
+  });
+
+  test('deny rules override allowed methods', async () => {
+    const { delegateWallet } = await addDelegate({
+      edit: target => {
+        target.deny = [['*_key']];
+      },
+    });
+    expect(await delegateWallet.check_call(callArgs('test_call'))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(callArgs('test_call_key'))).toEqual({
+      Deny: { MethodNotAllowed: null },
+    });
+  });
+
+  test('all_queries grants every method to proxy_query only', async () => {
+    const { delegateWallet } = await addDelegate({
+      edit: target => {
+        target.methods = [];
+        target.all_queries = [true];
+        target.deny = [['test_call_key']];
+      },
+    });
+    expect('Executed' in (await delegateWallet.proxy_query(callArgs('test_query')))).toBe(true);
+
+    expect(await delegateWallet.check_call(callArgs('test_query'))).toEqual({
+      Deny: { MethodNotAllowed: null },
+    });
+    expect(await delegateWallet.proxy_query(callArgs('test_call_key'))).toEqual({
+      Unauthorized: { reason: { MethodNotAllowed: null } },
+    });
+  });
+});
@@ -0,0 +1,184 @@
+import { _SERVICE as walletService, BatchCall, Method } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId, hasOwnProperty } from '@ego-js/utils';
+
+import { Principal } from '@dfinity/principal';
+import { IDL } from '@dfinity/candid';
+import { addDelegate, callArgs } from './proxyActor';
+
+describe('batch', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+  const keys = ['test_call_key'];
+
+  function batchCall(method_name: string, args_from: [] | [number] = []): BatchCall {
+    return { call: callArgs(method_name), args_from };
+  }
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('calls run in order', async () => {
+    const { delegateWallet } = await addDelegate({ keys });
+    const result = await delegateWallet.proxy_call_batch([batchCall('test_query'), batchCall('test_call')], {
+      Sequential: null,
+    });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(result.Ok.stopped_at).toEqual([]);
+    expect(result.Ok.results.every(r => hasOwnProperty(r, 'Executed'))).toBe(true);
+  });
+
+  test('a sequential batch runs nothing when a call is denied', async () => {
+    const { delegateWallet } = await addDelegate({ keys });
+    const result = await delegateWallet.proxy_call_batch(
+      [batchCall('test_query'), batchCall('not_a_method'), batchCall('test_call')],
+      { Sequential: null },
+    );
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(result.Ok.stopped_at).toEqual([1]);
+    expect(result.Ok.results).toEqual([{ Unauthorized: { reason: { MethodNotAllowed: null } } }]);
+  });
+
+  test('a sequential batch stops at a rejected call', async () => {
+    const { delegateWallet } = await addDelegate({ keys });
+    const rejected = batchCall('test_call');
+    rejected.call.args = [0];
+    const result = await delegateWallet.proxy_call_batch([batchCall('test_query'), rejected, batchCall('test_query')], {
+      Sequential: null,
+    });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(result.Ok.stopped_at).toEqual([1]);
+    expect(result.Ok.results.length).toBe(2);
+    expect(hasOwnProperty(result.Ok.results[0], 'Executed')).toBe(true);
+    expect(hasOwnProperty(result.Ok.results[1], 'Rejected')).toBe(true);
+  });
+
+  test('all or nothing stops at a rejected call', async () => {
+    const { delegateWallet } = await addDelegate({ keys });
+    const rejected = batchCall('test_call');
+    rejected.call.args = [0];
+    const result = await delegateWallet.proxy_call_batch([rejected, batchCall('test_query')], { AllOrNothing: null });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(result.Ok.stopped_at).toEqual([0]);
+    expect(result.Ok.results.length).toBe(1);
+    expect(hasOwnProperty(result.Ok.results[0], 'Rejected')).toBe(true);
+  });
+
+  test('every call of a batch is charged before the first runs', async () => {
+    const owner = await ownerActor;
+    const { delegate, delegateWallet } = await addDelegate({ cycles: { total: BigInt(1_500), per_call: [] } });
+
+    const calls = [batchCall('test_call'), batchCall('test_call')];
+    calls.forEach(c => (c.call.cycles = BigInt(1_000)));
+    const result = await delegateWallet.proxy_call_batch(calls, { AllOrNothing: null });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(result.Ok.stopped_at).toEqual([1]);
+    expect(result.Ok.results).toEqual([
+      { Unauthorized: { reason: { BudgetExceeded: { requested: BigInt(1_000), available: BigInt(500) } } } },
+    ]);
+    // nothing ran, so nothing stays charged
+    const [session] = await owner.get_expiry_user(delegate.getPrincipal());
+    expect(session!.cycles[0]!.total).toBe(BigInt(1_500));
+  });
+
+  test('a sequential batch with a key operation is queued as one unit', async () => {
+    const { delegateWallet } = await addDelegate({ keys });
+    const result = await delegateWallet.proxy_call_batch([batchCall('test_query'), batchCall('test_call_key')], {
+      Sequential: null,
+    });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(result.Ok.stopped_at).toEqual([0]);
+    expect(result.Ok.results.length).toBe(1);
+    expect(hasOwnProperty(result.Ok.results[0], 'Queued')).toBe(true);
+  });
+
+  test('all or nothing runs nothing when a call is denied', async () => {
+    const { delegateWallet } = await addDelegate({ keys });
+    const result = await delegateWallet.proxy_call_batch([batchCall('test_query'), batchCall('not_a_method')], {
+      AllOrNothing: null,
+    });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(result.Ok.stopped_at).toEqual([1]);
+    expect(result.Ok.results).toEqual([{ Unauthorized: { reason: { MethodNotAllowed: null } } }]);
+  });
+
+  test('a batch with a key operation is queued as one unit', async () => {
+    const owner = await ownerActor;
+    const { delegateWallet } = await addDelegate({ keys });
+    const result = await delegateWallet.proxy_call_batch([batchCall('test_query'), batchCall('test_call_key')], {
+      AllOrNothing: null,
+    });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    const queued = result.Ok.results[0];
+    if (result.Ok.results.length !== 1 || !hasOwnProperty(queued, 'Queued')) {
+      throw new Error('batch was not queued');
+    }
+
+    const reply = await owner.owner_confirm(queued.Queued.id, true);
+    expect(hasOwnProperty(reply, 'Ok') && hasOwnProperty(reply.Ok, 'Approved')).toBe(true);
+    const page = await owner.list_queue(
+      { to: [], status: [{ Approved: null }], from: [], user: [], method_name: [], canister: [] },
+      [],
+      100,
+    );
+    const item = page.items.find(v => v.item.hash === queued.Queued.id)!.item;
+    expect(item.batch[0]!.length).toBe(2);
+    expect(item.batch_results[0]!.length).toBe(2);
+  });
+
+  test('arguments taken from a reply are checked when an approved batch runs', async () => {
+    const owner = await ownerActor;
+    const { delegateWallet } = await addDelegate({
+      keys,
+      edit: target => {
+        const methods = target.methods as Array<[string, Method]>;
+        methods.find(([name]) => name === 'test_call_key')![1].arg_policy = [
+          { constraints: [], signature: ['(record { amount : nat })'] },
+        ];
+      },
+    });
+
+    // the arguments sent pass the policy, the reply of test_call they are replaced with doesn't
+    const amount = IDL.encode([IDL.Record({ amount: IDL.Nat })], [{ amount: BigInt(1) }]);
+    const key = batchCall('test_call_key', [0]);
+    key.call.args = Array.from(new Uint8Array(amount));
+    const result = await delegateWallet.proxy_call_batch([batchCall('test_call'), key], { AllOrNothing: null });
+    if (!hasOwnProperty(result, 'Ok') || !hasOwnProperty(result.Ok.results[0], 'Queued')) {
+      throw new Error('batch was not queued');
+    }
+    const id = result.Ok.results[0].Queued.id;
+
+    const reply = await owner.owner_confirm(id, true);
+    if (!hasOwnProperty(reply, 'Ok') || !hasOwnProperty(reply.Ok, 'Approved')) {
+      throw new Error('batch was not approved');
+    }
+    expect(reply.Ok.Approved).toEqual({ Err: expect.stringContaining('Call 1') });
+    expect(await owner.get_queue_status(id)).toEqual([{ Failed: null }]);
+  });
+
+  test('a call can only use the reply of an earlier call', async () => {
+    const result = await (await ownerActor).proxy_call_batch([batchCall('test_query', [0])], { Sequential: null });
+    expect(hasOwnProperty(result, 'Err')).toBe(true);
+  });
+});
@@ -0,0 +1,80 @@
+import { CyclesBudget, Method, _SERVICE as walletService } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId } from '@ego-js/utils';
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { Principal } from '@dfinity/principal';
+import { addDelegate, callArgs, Targets } from './proxyActor';
+
+describe('cycles budgets', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  // gives test_call a budget of its own
+  const methodBudget = (budget: CyclesBudget) => (target: Targets['targets'][number]) => {
+    const methods = target.methods as Array<[string, Method]>;
+    methods.find(([name]) => name === 'test_call')![1].cycles = [budget];
+  };
+
+  async function sessionTotal(delegate: Ed25519KeyIdentity) {
+    const [session] = await (await ownerActor).get_expiry_user(delegate.getPrincipal());
+    return session!.cycles[0]!.total;
+  }
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('calls above the per-call cap are denied', async () => {
+    const { delegateWallet } = await addDelegate({ cycles: { total: BigInt(10_000), per_call: [BigInt(1_000)] } });
+    expect(await delegateWallet.check_call(callArgs('test_call', BigInt(1_000)))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(callArgs('test_call', BigInt(1_001)))).toEqual({
+      Deny: { BudgetExceeded: { requested: BigInt(1_001), available: BigInt(1_000) } },
+    });
+    expect(await delegateWallet.proxy_call(callArgs('test_call', BigInt(1_001)))).toEqual({
+      Unauthorized: { reason: { BudgetExceeded: { requested: BigInt(1_001), available: BigInt(1_000) } } },
+    });
+  });
+
+  test('a method budget limits calls to that method', async () => {
+    const { delegateWallet } = await addDelegate({
+      cycles: { total: BigInt(10_000), per_call: [] },
+      edit: methodBudget({ total: BigInt(300), per_call: [] }),
+    });
+    expect(await delegateWallet.check_call(callArgs('test_call', BigInt(300)))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(callArgs('test_call', BigInt(301)))).toEqual({
+      Deny: { BudgetExceeded: { requested: BigInt(301), available: BigInt(300) } },
+    });
+  });
+
+  test('cycles are reserved while the call runs and unused cycles are refunded', async () => {
+    const { delegate, delegateWallet } = await addDelegate({ cycles: { total: BigInt(1_500), per_call: [] } });
+
+    // the first call holds 1000 cycles until it returns, so the second can't have them
+    const [first, second] = await Promise.all([
+      delegateWallet.proxy_call(callArgs('test_call', BigInt(1_000))),
+      delegateWallet.proxy_call(callArgs('test_call', BigInt(1_000))),
+    ]);
+    expect('Executed' in first).toBe(true);
+    expect(second).toEqual({
+      Unauthorized: { reason: { BudgetExceeded: { requested: BigInt(1_000), available: BigInt(500) } } },
+    });
+
+    // the test canister keeps none of the cycles, so all of them come back
+    expect(await sessionTotal(delegate)).toBe(BigInt(1_500));
+  });
+
+  test('refunds go back to the method budget too', async () => {
+    const { delegate, delegateWallet } = await addDelegate({
+      cycles: { total: BigInt(10_000), per_call: [] },
+      edit: methodBudget({ total: BigInt(300), per_call: [] }),
+    });
+    expect('Executed' in (await delegateWallet.proxy_call(callArgs('test_call', BigInt(300))))).toBe(true);
+    expect(await sessionTotal(delegate)).toBe(BigInt(10_000));
+    expect(await delegateWallet.check_call(callArgs('test_call', BigInt(300)))).toEqual({ Execute: null });
+  });
+});
@@ -0,0 +1,81 @@
+import { _SERVICE as walletService, CertifiedData } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId, hasOwnProperty } from '@ego-js/utils';
+
+import { Cbor, HashTree, lookup_path, reconstruct } from '@dfinity/agent';
+import { createHash } from 'crypto';
+import { Principal } from '@dfinity/principal';
+import { addDelegate, callArgs } from './proxyActor';
+
+const toHex = (buffer: ArrayBuffer) => Buffer.from(buffer).toString('hex');
+const sha256 = (bytes: Uint8Array | number[]) => createHash('sha256').update(Uint8Array.from(bytes)).digest('hex');
+const auditKey = (id: number) => {
+  const key = new DataView(new ArrayBuffer(8));
+  key.setBigUint64(0, BigInt(id));
+  return key.buffer;
+};
+
+describe('certified', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  // checks the witness against the certified data in the certificate and
+  // each value against its leaf, found in the witness under `paths`; the
+  // certificate signature itself is left to the agent
+  async function expectCertified(data: CertifiedData, paths: (string | ArrayBuffer)[][]) {
+    expect(data.certificate.length).toBe(1);
+    const certificate = Cbor.decode<{ tree: HashTree }>(new Uint8Array(data.certificate[0]!).buffer);
+    const certifiedData = lookup_path(
+      ['canister', Principal.fromText(walletCanisterId).toUint8Array(), 'certified_data'],
+      certificate.tree,
+    )!;
+    const witness = Cbor.decode<HashTree>(new Uint8Array(data.witness).buffer);
+    const root = await reconstruct(witness);
+    expect(toHex(root)).toEqual(toHex(certifiedData));
+    expect(data.values.length).toBe(paths.length);
+    data.values.forEach((value, i) => {
+      const leaf = lookup_path(paths[i], witness);
+      expect(leaf).toBeDefined();
+      expect(toHex(leaf!)).toEqual(sha256(value));
+    });
+  }
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ ALL: null });
+  });
+
+  afterAll(async () => {
+    await (await ownerActor).set_method_validate_type({ KEY: null });
+  });
+
+  test('queued calls are certified', async () => {
+    const { delegateWallet } = await addDelegate();
+
+    const result = await delegateWallet.proxy_call(callArgs('test_call'));
+    if (!hasOwnProperty(result, 'Queued')) {
+      throw new Error('call was not queued');
+    }
+    const data = await delegateWallet.get_certified_queue_method(result.Queued.id);
+    expect(data.values.length).toBe(1);
+    await expectCertified(data, [['queue', result.Queued.id]]);
+
+    const missing = await delegateWallet.get_certified_queue_method('not_a_queue_id');
+    expect(missing.values).toEqual([]);
+    await expectCertified(missing, []);
+    const missingWitness = Cbor.decode<HashTree>(new Uint8Array(missing.witness).buffer);
+    expect(lookup_path(['queue', 'not_a_queue_id'], missingWitness)).toBeUndefined();
+  });
+
+  test('the audit log is certified', async () => {
+    const owner = await ownerActor;
+    await owner.proxy_call(callArgs('test_call'));
+
+    const data = await owner.get_certified_audit_log(BigInt(0), 10);
+    expect(data.values.length).toBeGreaterThan(0);
+    await expectCertified(data, data.values.map((_, id) => ['audit', auditKey(id)]));
+  });
+});
@@ -0,0 +1,62 @@
+import { _SERVICE as walletService } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId } from '@ego-js/utils';
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { Principal } from '@dfinity/principal';
+import { IDL } from '@dfinity/candid';
+import { addDelegate, callArgs } from './proxyActor';
+
+describe('dfx cycles wallet interface', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('wallet_call and wallet_call128 forward the owner calls', async () => {
+    const owner = await ownerActor;
+    const call = callArgs('test_call');
+    for (const result of [await owner.wallet_call(call), await owner.wallet_call128(call)]) {
+      if (!('Ok' in result)) {
+        throw new Error(result.Err);
+      }
+      const reply = IDL.decode([IDL.Opt(IDL.Text)], new Uint8Array(result.Ok.return).buffer);
+      expect(reply[0]).toEqual(['value']);
+    }
+  });
+
+  test('delegate calls go through the same authorization', async () => {
+    const stranger = await getActor<walletService>(Ed25519KeyIdentity.generate(), walletIDL, walletCanisterId);
+    expect(await stranger.wallet_call(callArgs('test_call'))).toEqual({ Err: 'caller is not a delegate' });
+
+    const { delegateWallet } = await addDelegate();
+
+    expect('Ok' in (await delegateWallet.wallet_call(callArgs('test_call')))).toBe(true);
+    // the management canister is not among the delegate's targets
+    expect(
+      await delegateWallet.wallet_send({ canister: Principal.fromText(targetCanisterId), amount: BigInt(1) }),
+    ).toEqual({ Err: 'canister is not in authorized targets' });
+  });
+
+  test('the balance is for the owner and delegates only', async () => {
+    const owner = await ownerActor;
+    const balance = await owner.wallet_balance();
+    const balance128 = await owner.wallet_balance128();
+    expect(balance.amount > BigInt(0)).toBe(true);
+    expect(balance128.amount > BigInt(0)).toBe(true);
+
+    const stranger = await getActor<walletService>(Ed25519KeyIdentity.generate(), walletIDL, walletCanisterId);
+    await expect(stranger.wallet_balance()).rejects.toBeTruthy();
+  });
+
+  test('anyone may call wallet_receive', async () => {
+    const stranger = await getActor<walletService>(Ed25519KeyIdentity.generate(), walletIDL, walletCanisterId);
+    await expect(stranger.wallet_receive([{ memo: ['top up'] }])).resolves.toBeUndefined();
+  });
+});
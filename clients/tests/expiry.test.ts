@@ -0,0 +1,99 @@
+import { _SERVICE as walletService } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId } from '@ego-js/utils';
+
+import { Principal } from '@dfinity/principal';
+import { addDelegate, sleep } from './proxyActor';
+
+const SECOND = BigInt(1000 * 1000 * 1000);
+const DEFAULT_PERIOD = BigInt(7 * 24 * 60 * 60) * SECOND;
+
+describe('expiry', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  const testArgs = {
+    map: Array.from<[number, boolean]>([
+      [0, true],
+      [1, false],
+    ]),
+    pid: Principal.fromText(walletCanisterId),
+    str: 'expiry',
+    bytes: Array.from([0, 1, 2, 3, 4]),
+  };
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+    await owner.set_expiry_period(DEFAULT_PERIOD);
+  });
+
+  afterAll(async () => {
+    await (await ownerActor).set_expiry_period(DEFAULT_PERIOD);
+  });
+
+  test('session ends at timestamp plus the requested expiration', async () => {
+    const { session } = await addDelegate({ expiration: BigInt(60) * SECOND });
+    expect(session.expiry_timestamp - session.timestamp).toBe(BigInt(60) * SECOND);
+  });
+
+  test('global expiry period is only the default', async () => {
+    const { session } = await addDelegate();
+    expect(session.expiry_timestamp - session.timestamp).toBe(DEFAULT_PERIOD);
+  });
+
+  test('changing the global period does not touch existing sessions', async () => {
+    const { delegate, proxyActor } = await addDelegate({ expiration: BigInt(60) * SECOND });
+    await (await ownerActor).set_expiry_period(BigInt(1));
+
+    await expect(proxyActor.test_query(testArgs)).resolves.toEqual(['query']);
+    expect((await (await ownerActor).get_expiry_user(delegate.getPrincipal())).length).toBe(1);
+
+    await (await ownerActor).set_expiry_period(DEFAULT_PERIOD);
+  });
+
+  test('adding a delegate keeps other live sessions', async () => {
+    const first = await addDelegate({ expiration: BigInt(60) * SECOND });
+    await addDelegate({ expiration: BigInt(60) * SECOND });
+    expect((await (await ownerActor).get_expiry_user(first.delegate.getPrincipal())).length).toBe(1);
+  });
+
+  test('session is rejected once expiry_timestamp has passed', async () => {
+    const { delegate, proxyActor } = await addDelegate({ expiration: BigInt(3) * SECOND });
+    await expect(proxyActor.test_query(testArgs)).resolves.toEqual(['query']);
+
+    await sleep(5000);
+    await expect(proxyActor.test_query(testArgs)).rejects.toBeTruthy();
+    expect((await (await ownerActor).get_expiry_user(delegate.getPrincipal())).length).toBe(0);
+  });
+
+  test('extend moves the end of a session later', async () => {
+    const { delegate, session, proxyActor } = await addDelegate({ expiration: BigInt(3) * SECOND });
+    const extended = await (await ownerActor).extend_expiry_user(delegate.getPrincipal(), BigInt(60) * SECOND);
+    expect(extended[0]!.expiry_timestamp).toBe(session.expiry_timestamp + BigInt(60) * SECOND);
+
+    await sleep(5000);
+    await expect(proxyActor.test_query(testArgs)).resolves.toEqual(['query']);
+  });
+
+  test('shorten into the past expires the session', async () => {
+    const { delegate, session, proxyActor } = await addDelegate({ expiration: BigInt(60) * SECOND });
+    const shortened = await (await ownerActor).shorten_expiry_user(delegate.getPrincipal(), BigInt(60) * SECOND);
+    expect(shortened[0]!.expiry_timestamp).toBe(session.timestamp);
+
+    await expect(proxyActor.test_query(testArgs)).rejects.toBeTruthy();
+    expect((await (await ownerActor).extend_expiry_user(delegate.getPrincipal(), BigInt(60) * SECOND)).length).toBe(0);
+  });
+
+  test('revoke ends a session immediately', async () => {
+    const { delegate, proxyActor } = await addDelegate({ expiration: BigInt(60) * SECOND });
+    const revoked = await (await ownerActor).revoke_expiry_user(delegate.getPrincipal());
+    expect(revoked.length).toBe(1);
+
+    await expect(proxyActor.test_query(testArgs)).rejects.toBeTruthy();
+    expect((await (await ownerActor).get_expiry_user(delegate.getPrincipal())).length).toBe(0);
+  });
+});
@@ -0,0 +1,153 @@
+import { _SERVICE as walletService, NftPolicy } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId } from '@ego-js/utils';
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { Principal } from '@dfinity/principal';
+import { IDL } from '@dfinity/candid';
+import { method, newDelegate } from './proxyActor';
+
+// test_canister stands in for a collection: only decisions are checked, nothing is sent
+describe('nft policies', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const collectionId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  const Account = IDL.Record({ owner: IDL.Principal, subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)) });
+  const TransferArg = IDL.Record({
+    from_subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    to: Account,
+    token_id: IDL.Nat,
+    memo: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    created_at_time: IDL.Opt(IDL.Nat64),
+  });
+
+  const User = IDL.Variant({ address: IDL.Text, principal: IDL.Principal });
+  const TransferRequest = IDL.Record({
+    from: User,
+    to: User,
+    token: IDL.Text,
+    notify: IDL.Bool,
+    memo: IDL.Vec(IDL.Nat8),
+    subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    amount: IDL.Nat,
+  });
+
+  const alice = Ed25519KeyIdentity.generate().getPrincipal();
+  const bob = Ed25519KeyIdentity.generate().getPrincipal();
+
+  function callArgs(method_name: string, args: ArrayBuffer) {
+    return {
+      canister: Principal.fromText(collectionId),
+      method_name,
+      args: Array.from(new Uint8Array(args)),
+      cycles: BigInt(0),
+    };
+  }
+
+  function icrc7Transfer(transfers: [Principal, bigint][]) {
+    const batch = transfers.map(([owner, token_id]) => ({
+      from_subaccount: [],
+      to: { owner, subaccount: [] },
+      token_id,
+      memo: [],
+      created_at_time: [],
+    }));
+    return callArgs('icrc7_transfer', IDL.encode([IDL.Vec(TransferArg)], [batch]));
+  }
+
+  function extTransfer(to: Principal, token: string) {
+    const request = {
+      from: { principal: Principal.fromText(walletCanisterId) },
+      to: { principal: to },
+      token,
+      notify: false,
+      memo: [],
+      subaccount: [],
+      amount: BigInt(1),
+    };
+    return callArgs('transfer', IDL.encode([TransferRequest], [request]));
+  }
+
+  function policy(fields: Partial<NftPolicy>): NftPolicy {
+    return { token_ids: [], max_transfers: [], recipients: [], requires_approval: false, ...fields };
+  }
+
+  async function addDelegate(nft_policy: NftPolicy) {
+    const { delegate, delegateWallet } = await newDelegate();
+    await (
+      await ownerActor
+    ).add_expiry_user(delegate.getPrincipal(), {
+      expiration: [],
+      cycles: [],
+      rate_limit: [],
+      targets: [
+        {
+          canister: Principal.fromText(collectionId),
+          methods: [method('icrc7_transfer'), method('transfer')],
+          rate_limit: [],
+          deny: [],
+          all_queries: [],
+          token_policy: [],
+          nft_policy: [nft_policy],
+        },
+      ],
+    });
+    return delegateWallet;
+  }
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(collectionId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('only allowed tokens go to allowed recipients', async () => {
+    const delegateWallet = await addDelegate(
+      policy({
+        token_ids: [['1', '2', 'ext-token']],
+        recipients: [[{ owner: alice, subaccount: [] }]],
+      }),
+    );
+    expect(await delegateWallet.check_call(icrc7Transfer([[alice, BigInt(1)], [alice, BigInt(2)]]))).toEqual({
+      Execute: null,
+    });
+    expect(await delegateWallet.check_call(extTransfer(alice, 'ext-token'))).toEqual({ Execute: null });
+
+    const rejected = [
+      icrc7Transfer([[alice, BigInt(1)], [alice, BigInt(3)]]),
+      icrc7Transfer([[bob, BigInt(1)]]),
+      extTransfer(alice, 'other-token'),
+      extTransfer(bob, 'ext-token'),
+    ];
+    for (const args of rejected) {
+      const denied = await delegateWallet.check_call(args);
+      expect('Deny' in denied && 'TransferRejected' in denied.Deny).toBe(true);
+    }
+  });
+
+  test('a session moves at most max_transfers tokens', async () => {
+    const delegateWallet = await addDelegate(policy({ max_transfers: [2] }));
+    expect(await delegateWallet.check_call(icrc7Transfer([[alice, BigInt(1)], [bob, BigInt(2)]]))).toEqual({
+      Execute: null,
+    });
+    expect(
+      await delegateWallet.check_call(icrc7Transfer([[alice, BigInt(1)], [alice, BigInt(2)], [alice, BigInt(3)]])),
+    ).toEqual({
+      Deny: { AllowanceExceeded: { requested: BigInt(3), remaining: BigInt(2) } },
+    });
+  });
+
+  test('requires_approval queues every transfer', async () => {
+    const delegateWallet = await addDelegate(policy({ requires_approval: true }));
+    expect(await delegateWallet.check_call(icrc7Transfer([[alice, BigInt(1)]]))).toEqual({ Queue: null });
+    expect(await delegateWallet.check_call(extTransfer(alice, 'ext-token'))).toEqual({ Queue: null });
+  });
+
+  test('arguments that are not a transfer are rejected', async () => {
+    const delegateWallet = await addDelegate(policy({}));
+    const denied = await delegateWallet.check_call(callArgs('icrc7_transfer', IDL.encode([], [])));
+    expect('Deny' in denied && 'TransferRejected' in denied.Deny).toBe(true);
+  });
+});
@@ -0,0 +1,103 @@
+import { ArgPolicy, ArgRule, Decision, Method, _SERVICE as walletService } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId } from '@ego-js/utils';
+
+import { Principal } from '@dfinity/principal';
+import { IDL } from '@dfinity/candid';
+import { addDelegate, callArgs, TestArgs } from './proxyActor';
+
+describe('argument policies', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+  const allowed = Principal.fromText(walletCanisterId);
+  const other = Principal.fromText(targetCanisterId);
+
+  // the id candid gives a field name, as a path segment that addresses it by hash
+  function fieldHash(name: string) {
+    let hash = 0;
+    for (const byte of new TextEncoder().encode(name)) {
+      hash = (hash * 223 + byte) >>> 0;
+    }
+    return String(hash);
+  }
+
+  function testArgs(pid: Principal, str: string) {
+    return { map: [], pid, str, bytes: [] };
+  }
+
+  // test_call with `values` encoded as `types`
+  function encodedCall(types: IDL.Type[], values: unknown[]) {
+    return { ...callArgs('test_call'), args: Array.from(new Uint8Array(IDL.encode(types, values))) };
+  }
+
+  async function addPolicyDelegate(arg_policy: ArgPolicy) {
+    const { delegateWallet } = await addDelegate({
+      edit: target => {
+        const methods = target.methods as Array<[string, Method]>;
+        methods.find(([name]) => name === 'test_call')![1].arg_policy = [arg_policy];
+      },
+    });
+    return delegateWallet;
+  }
+
+  function constraint(path: string[], rule: ArgRule): ArgPolicy {
+    return { constraints: [{ arg: 0, path, rule }], signature: [] };
+  }
+
+  function expectRejected(decision: Decision, reason = '') {
+    expect('Deny' in decision && 'ArgumentRejected' in decision.Deny).toBe(true);
+    if ('Deny' in decision && 'ArgumentRejected' in decision.Deny) {
+      expect(decision.Deny.ArgumentRejected.reason).toContain(reason);
+    }
+  }
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(other);
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('a named field path is checked with PrincipalEq', async () => {
+    const delegateWallet = await addPolicyDelegate(constraint(['pid'], { PrincipalEq: allowed }));
+    expect(await delegateWallet.check_call(encodedCall([TestArgs], [testArgs(allowed, 'a')]))).toEqual({
+      Execute: null,
+    });
+    expectRejected(await delegateWallet.check_call(encodedCall([TestArgs], [testArgs(other, 'a')])));
+  });
+
+  test('a hashed field path is checked with TextIn', async () => {
+    const delegateWallet = await addPolicyDelegate(constraint([fieldHash('str')], { TextIn: ['a', 'b'] }));
+    expect(await delegateWallet.check_call(encodedCall([TestArgs], [testArgs(allowed, 'b')]))).toEqual({
+      Execute: null,
+    });
+    expectRejected(await delegateWallet.check_call(encodedCall([TestArgs], [testArgs(allowed, 'c')])));
+  });
+
+  test('opt values are looked through and checked with PrincipalIn', async () => {
+    const delegateWallet = await addPolicyDelegate(constraint(['pid'], { PrincipalIn: [allowed] }));
+    const types = [IDL.Opt(TestArgs)];
+    expect(await delegateWallet.check_call(encodedCall(types, [[testArgs(allowed, 'a')]]))).toEqual({ Execute: null });
+    expectRejected(await delegateWallet.check_call(encodedCall(types, [[testArgs(other, 'a')]])));
+    // a missing value has no field to check
+    expectRejected(await delegateWallet.check_call(encodedCall(types, [[]])));
+  });
+
+  test('amounts are checked with NatMax', async () => {
+    const delegateWallet = await addPolicyDelegate(constraint(['amount'], { NatMax: BigInt(100) }));
+    const types = [IDL.Record({ amount: IDL.Nat })];
+    expect(await delegateWallet.check_call(encodedCall(types, [{ amount: BigInt(100) }]))).toEqual({ Execute: null });
+    expectRejected(await delegateWallet.check_call(encodedCall(types, [{ amount: BigInt(101) }])));
+  });
+
+  test('args that do not decode under the signature are rejected', async () => {
+    const delegateWallet = await addPolicyDelegate({ constraints: [], signature: ['(record { amount : nat })'] });
+    const types = [IDL.Record({ amount: IDL.Nat })];
+    expect(await delegateWallet.check_call(encodedCall(types, [{ amount: BigInt(1) }]))).toEqual({ Execute: null });
+    expectRejected(
+      await delegateWallet.check_call(encodedCall([IDL.Text], ['not an amount'])),
+      'Arguments do not match the method signature',
+    );
+  });
+});
@@ -1,8 +1,11 @@
-import { CallResult, Method, MethodType, _SERVICE as walletService } from '@/idls/wallet_canister';
+import { CallCanisterArgs, CallResult, CyclesBudget, Method, MethodType, NftPolicy, RateLimit, TokenPolicy, _SERVICE as walletService } from '@/idls/wallet_canister';
 import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+import { _SERVICE as targetService } from '@/idls/test_canister';
+import { idlFactory as targetIDL } from '@/idls/test_canister.idl';
 import { getActor, getCanisterId, hasOwnProperty, identity } from '@ego-js/utils';
 import { Actor, ActorConfig, ActorConstructor, ActorMethod, ActorSubclass, CallConfig, CreateCertificateOptions } from '@dfinity/agent';
 import { strategy } from '@dfinity/agent/lib/cjs/polling';
+import { Ed25519KeyIdentity } from '@dfinity/identity';
 import { Principal } from '@dfinity/principal';
 
 import { IDL } from '@dfinity/candid';
@@ -19,9 +22,16 @@ export interface ActorItem<T> extends BaseActorItem {
 
 export interface Targets {
   expiration: [] | [bigint];
+  cycles: [] | [CyclesBudget];
+  rate_limit: [] | [RateLimit];
   targets: {
     canister: Principal;
     methods: [] | Array<[string, Method]>;
+    rate_limit: [] | [RateLimit];
+    deny: [] | [string[]];
+    all_queries: [] | [boolean];
+    token_policy: [] | [TokenPolicy];
+    nft_policy: [] | [NftPolicy];
   }[];
 }
 
@@ -31,7 +41,7 @@ const DEFAULT_ACTOR_CONFIG = {
 
 function _createProxyActor(wallet_call: ActorSubclass<walletService>, canister: string, idl: IDL.InterfaceFactory): [ActorConstructor, Method[]] {
   const service = idl({ IDL });
-  const methods = service._fields.map(f => {
+  const methods = service._fields.map((f): Method => {
     let method_type: MethodType;
     switch (f[1].annotations[0]) {
       case '' || 'update':
@@ -56,6 +66,10 @@ function _createProxyActor(wallet_call: ActorSubclass<walletService>, canister:
       name: f[0],
       method_type,
       key_operation: false,
+      cycles: [],
+      arg_policy: [],
+      approval_threshold: [],
+      rate_limit: [],
     };
   });
 
@@ -121,14 +135,17 @@ function _createActorMethod(wallet_call: ActorSubclass<walletService>, canister:
       method_name,
       canister,
     });
-    if (hasOwnProperty(response, 'Ok')) {
-      const responseBytes = response.Ok.return;
+    if (hasOwnProperty(response, 'Executed')) {
+      const responseBytes = response.Executed.return;
       return decodeReturnValue(newFunc.retTypes, new Uint8Array(responseBytes));
+    } else if (hasOwnProperty(response, 'Rejected')) {
+      throw new Error(`Call rejected: ${response.Rejected.reject_code}: ${response.Rejected.message}`);
+    } else if (hasOwnProperty(response, 'Unauthorized')) {
+      throw new Error(`Call unauthorized: ${Object.keys(response.Unauthorized.reason)[0]}`);
+    } else if (hasOwnProperty(response, 'Submitted')) {
+      return undefined;
     } else {
-      // console.log(response.Err);
-      // throw new Error(response.Err);
-
-      const pollResult = await pollQueueMethod(wallet_call, response.Err, 3, 3000);
+      const pollResult = await pollQueueMethod(wallet_call, response.Queued.id, 3, 3000);
       if (pollResult.reject === true) {
         throw new Error('Owner has rejected this call');
       } else {
@@ -159,7 +176,12 @@ export interface KeyOperation {
 }
 
 export class ProxyTargets<T extends BaseActorItem> {
-  constructor(private actors: T[], private expiration?: bigint) {}
+  constructor(
+    private actors: T[],
+    private expiration?: bigint,
+    private cycles?: CyclesBudget,
+    private rateLimit?: RateLimit,
+  ) {}
 
   public buildTargets(keyOperations?: KeyOperation[]): Targets {
     if (keyOperations) {
@@ -185,16 +207,77 @@ export class ProxyTargets<T extends BaseActorItem> {
       return {
         canister: Principal.fromText(e.canister),
         methods,
+        rate_limit: [] as [] | [RateLimit],
+        deny: [] as [] | [string[]],
+        all_queries: [] as [] | [boolean],
+        token_policy: [] as [] | [TokenPolicy],
+        nft_policy: [] as [] | [NftPolicy],
       };
     });
 
     return {
       expiration: this.expiration ? [this.expiration] : [],
+      cycles: this.cycles ? [this.cycles] : [],
+      rate_limit: this.rateLimit ? [this.rateLimit] : [],
       targets,
     };
   }
 }
 
+// the argument of test_canister's test_call, test_call_key and test_query
+export const TestArgs = IDL.Record({
+  map: IDL.Vec(IDL.Tuple(IDL.Nat32, IDL.Bool)),
+  pid: IDL.Principal,
+  str: IDL.Text,
+  bytes: IDL.Vec(IDL.Nat8),
+});
+
+// a call of `method_name` on test_canister, with arguments it accepts
+export function callArgs(method_name: string, cycles = BigInt(0)): CallCanisterArgs {
+  const args = IDL.encode([TestArgs], [{ map: [[0, true]], pid: Principal.fromText(getCanisterId('wallet_canister')!), str: 'test', bytes: [0, 1] }]);
+  return {
+    canister: Principal.fromText(getCanisterId('test_canister')!),
+    method_name,
+    args: Array.from(new Uint8Array(args)),
+    cycles,
+  };
+}
+
+// an entry of a target's methods
+export function method(name: string, method_type: MethodType = { CALL: null }): [string, Method] {
+  return [name, { name, method_type, key_operation: false, cycles: [], arg_policy: [], approval_threshold: [], rate_limit: [] }];
+}
+
+export interface DelegateOptions {
+  delegate?: Ed25519KeyIdentity;
+  expiration?: bigint;
+  cycles?: CyclesBudget;
+  rateLimit?: RateLimit;
+  // test_canister methods registered as key operations
+  keys?: string[];
+  // changes the test_canister target before the session is added
+  edit?: (target: Targets['targets'][number]) => void;
+}
+
+// a new identity and the wallet as it sees it, not yet a delegate
+export async function newDelegate(delegate = Ed25519KeyIdentity.generate()) {
+  const delegateWallet = await getActor<walletService>(delegate, walletIDL, getCanisterId('wallet_canister')!);
+  return { delegate, delegateWallet };
+}
+
+// adds a session for a new identity on the methods of test_canister
+export async function addDelegate(options: DelegateOptions = {}) {
+  const { delegate, delegateWallet } = await newDelegate(options.delegate);
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const proxyActorItem = createProxyActor<targetService>(delegateWallet, targetCanisterId, targetIDL);
+  const keyOperations = options.keys && [{ canister: targetCanisterId, keys: options.keys }];
+  const targets = new ProxyTargets([proxyActorItem], options.expiration, options.cycles, options.rateLimit).buildTargets(keyOperations);
+  options.edit?.(targets.targets[0]);
+  const owner = await getActor<walletService>(identity(), walletIDL, getCanisterId('wallet_canister')!);
+  const session = await owner.add_expiry_user(delegate.getPrincipal(), targets);
+  return { delegate, delegateWallet, session, proxyActor: proxyActorItem.actor };
+}
+
 export interface PollResult {
   result:
     | {
@@ -0,0 +1,218 @@
+import { _SERVICE as walletService, QueueFilter } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId, hasOwnProperty } from '@ego-js/utils';
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { Principal } from '@dfinity/principal';
+import { addDelegate, callArgs } from './proxyActor';
+
+const DAY = BigInt(24 * 60 * 60) * BigInt(1000 * 1000 * 1000);
+
+const noFilter: QueueFilter = { to: [], status: [], from: [], user: [], method_name: [], canister: [] };
+
+describe('queue', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  async function queueCall(delegate?: Ed25519KeyIdentity) {
+    const { delegateWallet } = await addDelegate({ delegate });
+    const result = await delegateWallet.proxy_call(callArgs('test_call'));
+    if (!hasOwnProperty(result, 'Queued')) {
+      throw new Error('call was not queued');
+    }
+    return result.Queued.id;
+  }
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ ALL: null });
+  });
+
+  afterAll(async () => {
+    await (await ownerActor).set_method_validate_type({ KEY: null });
+  });
+
+  test('a queued call is pending', async () => {
+    const id = await queueCall();
+    expect(await (await ownerActor).get_queue_status(id)).toEqual([{ Pending: null }]);
+  });
+
+  test('a confirmed call is not executed again', async () => {
+    const owner = await ownerActor;
+    const id = await queueCall();
+
+    const first = await owner.owner_confirm(id, true);
+    expect(hasOwnProperty(first, 'Ok')).toBe(true);
+    expect(await owner.get_queue_status(id)).toEqual([{ Approved: null }]);
+
+    const second = await owner.owner_confirm(id, true);
+    expect(hasOwnProperty(second, 'Err')).toBe(true);
+  });
+
+  test('concurrent confirmations execute once', async () => {
+    const owner = await ownerActor;
+    const id = await queueCall();
+
+    const replies = await Promise.all([owner.owner_confirm(id, true), owner.owner_confirm(id, true)]);
+    expect(replies.filter(r => hasOwnProperty(r, 'Ok')).length).toBe(1);
+  });
+
+  test('a call past its deadline expires', async () => {
+    const owner = await ownerActor;
+    await owner.set_queue_approval_period(BigInt(1));
+    try {
+      const id = await queueCall();
+      expect(hasOwnProperty(await owner.owner_confirm(id, true), 'Err')).toBe(true);
+      expect(await owner.get_queue_status(id)).toEqual([{ Expired: null }]);
+    } finally {
+      await owner.set_queue_approval_period(DAY);
+    }
+  });
+
+  test('calls of a revoked session expire', async () => {
+    const owner = await ownerActor;
+    const delegate = Ed25519KeyIdentity.generate();
+    const id = await queueCall(delegate);
+    await owner.revoke_expiry_user(delegate.getPrincipal());
+    expect(hasOwnProperty(await owner.owner_confirm(id, true), 'Err')).toBe(true);
+    expect(await owner.get_queue_status(id)).toEqual([{ Expired: null }]);
+  });
+
+  test('calls of a shortened session expire with it', async () => {
+    const owner = await ownerActor;
+    const delegate = Ed25519KeyIdentity.generate();
+    const id = await queueCall(delegate);
+    await owner.shorten_expiry_user(delegate.getPrincipal(), DAY * BigInt(365));
+    expect(hasOwnProperty(await owner.owner_confirm(id, true), 'Err')).toBe(true);
+    expect(await owner.get_queue_status(id)).toEqual([{ Expired: null }]);
+  });
+
+  test('unconfirmed calls are the pending ones', async () => {
+    const owner = await ownerActor;
+    const id = await queueCall();
+    const user = (await owner.list_queue({ ...noFilter, status: [{ Pending: null }] }, [], 100)).items.find(
+      v => v.item.hash === id,
+    )!.item.user;
+    expect((await owner.get_queue_unconfirmed(user)).map(q => q.hash)).toEqual([id]);
+
+    await owner.owner_confirm(id, false);
+    expect(await owner.get_queue_unconfirmed(user)).toEqual([]);
+  });
+
+  test('queued calls keep the method type they were queued with', async () => {
+    const owner = await ownerActor;
+    const id = await queueCall();
+    const item = (await owner.list_queue({ ...noFilter, status: [{ Pending: null }] }, [], 100)).items.find(
+      v => v.item.hash === id,
+    )!.item;
+    expect(item.method_types).toEqual([[{ CALL: null }]]);
+    await owner.owner_confirm(id, false);
+  });
+
+  test('list_queue filters and pages', async () => {
+    const owner = await ownerActor;
+    const first = await queueCall();
+    const second = await queueCall();
+    const filter: QueueFilter = { ...noFilter, status: [{ Pending: null }], method_name: ['test_call'] };
+
+    const ids: string[] = [];
+    let cursor: [] | [string] = [];
+    do {
+      const page = await owner.list_queue(filter, cursor, 1);
+      expect(page.items.length).toBeLessThanOrEqual(1);
+      page.items.forEach(v => {
+        expect(v.status).toEqual({ Pending: null });
+        ids.push(v.item.hash);
+      });
+      cursor = page.next_cursor;
+    } while (cursor.length > 0);
+
+    expect(ids).toEqual(expect.arrayContaining([first, second]));
+    expect((await owner.list_queue({ ...noFilter, method_name: ['not_a_method'] }, [], 100)).items).toEqual([]);
+  });
+
+  test('confirming an unknown call finds nothing', async () => {
+    expect(await (await ownerActor).owner_confirm('not a queued call', true)).toEqual({ Ok: { NotFound: null } });
+  });
+
+  test('a rejected call can not be approved', async () => {
+    const owner = await ownerActor;
+    const id = await queueCall();
+
+    await owner.owner_confirm(id, false);
+    expect(await owner.get_queue_status(id)).toEqual([{ Rejected: null }]);
+    expect(hasOwnProperty(await owner.owner_confirm(id, true), 'Err')).toBe(true);
+  });
+
+  describe('with approvers', () => {
+    const first = Ed25519KeyIdentity.generate();
+    const second = Ed25519KeyIdentity.generate();
+    const approverActor = (id: Ed25519KeyIdentity) => getActor<walletService>(id, walletIDL, walletCanisterId);
+
+    beforeAll(async () => {
+      const owner = await ownerActor;
+      const ownerPrincipal = identity().getPrincipal();
+      const result = await owner.set_approvers([ownerPrincipal, first.getPrincipal(), second.getPrincipal()], 2);
+      expect(hasOwnProperty(result, 'Ok')).toBe(true);
+    });
+
+    afterAll(async () => {
+      await (await ownerActor).set_approvers([], 1);
+    });
+
+    test('a threshold larger than the approvers is refused', async () => {
+      const result = await (await ownerActor).set_approvers([first.getPrincipal()], 2);
+      expect(hasOwnProperty(result, 'Err')).toBe(true);
+    });
+
+    test('a call runs once the threshold is met', async () => {
+      const owner = await ownerActor;
+      const id = await queueCall();
+
+      expect(await owner.owner_confirm(id, true)).toEqual({ Ok: { Pending: { approvals: 1, threshold: 2 } } });
+
+      const reply = await (await approverActor(first)).owner_confirm(id, true);
+      expect(hasOwnProperty(reply, 'Ok') && hasOwnProperty(reply.Ok, 'Approved')).toBe(true);
+      expect(await owner.get_queue_status(id)).toEqual([{ Approved: null }]);
+
+      const item = (await owner.list_queue({ ...noFilter, status: [{ Approved: null }] }, [], 100)).items.find(
+        v => v.item.hash === id,
+      )!;
+      expect(item.item.votes[0]!.length).toBe(2);
+    });
+
+    test('a call is rejected once the threshold is out of reach', async () => {
+      const owner = await ownerActor;
+      const id = await queueCall();
+
+      const reply = await (await approverActor(first)).owner_confirm(id, false);
+      expect(reply).toEqual({ Ok: { Pending: { approvals: 0, threshold: 2 } } });
+
+      await (await approverActor(second)).owner_confirm(id, false);
+      expect(await owner.get_queue_status(id)).toEqual([{ Rejected: null }]);
+    });
+
+    test('votes of removed approvers do not count', async () => {
+      const owner = await ownerActor;
+      const ownerPrincipal = identity().getPrincipal();
+      const id = await queueCall();
+
+      await (await approverActor(first)).owner_confirm(id, true);
+      await owner.set_approvers([ownerPrincipal, second.getPrincipal()], 2);
+      try {
+        expect(await owner.owner_confirm(id, true)).toEqual({ Ok: { Pending: { approvals: 1, threshold: 2 } } });
+      } finally {
+        await owner.set_approvers([ownerPrincipal, first.getPrincipal(), second.getPrincipal()], 2);
+      }
+    });
+
+    test('others can not vote', async () => {
+      const stranger = await approverActor(Ed25519KeyIdentity.generate());
+      const id = await queueCall();
+      await expect(stranger.owner_confirm(id, true)).rejects.toBeTruthy();
+    });
+  });
+});
@@ -0,0 +1,109 @@
+import { Method, RateLimit, _SERVICE as walletService } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId, hasOwnProperty } from '@ego-js/utils';
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { Principal } from '@dfinity/principal';
+import { addDelegate, callArgs } from './proxyActor';
+
+describe('rate limits', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('a method limit denies calls over it', async () => {
+    const owner = await ownerActor;
+    const { delegate, delegateWallet } = await addDelegate({
+      edit: target => {
+        const methods = target.methods as Array<[string, Method]>;
+        const limit: RateLimit = { per_minute: [2], per_hour: [], per_day: [] };
+        methods.find(([name]) => name === 'test_query')![1].rate_limit = [limit];
+      },
+    });
+
+    expect(hasOwnProperty(await delegateWallet.proxy_call(callArgs('test_query')), 'Executed')).toBe(true);
+    expect(hasOwnProperty(await delegateWallet.proxy_call(callArgs('test_query')), 'Executed')).toBe(true);
+    const denied = await delegateWallet.proxy_call(callArgs('test_query'));
+    if (!hasOwnProperty(denied, 'Unauthorized') || !hasOwnProperty(denied.Unauthorized.reason, 'RateLimited')) {
+      throw new Error('call was not rate limited');
+    }
+    expect(denied.Unauthorized.reason.RateLimited.limit).toBe(2);
+
+    // other methods are not limited
+    expect(hasOwnProperty(await delegateWallet.proxy_call(callArgs('test_call')), 'Executed')).toBe(true);
+
+    const counters = await owner.get_rate_counters();
+    const counter = counters.find(
+      c => hasOwnProperty(c.key, 'Method') && c.key.Method[0].toText() === delegate.getPrincipal().toText(),
+    )!;
+    expect(counter.last_minute).toBe(2);
+  });
+
+  test('methods granted by one pattern share its limit', async () => {
+    const { delegateWallet } = await addDelegate({
+      edit: target => {
+        const [, method] = target.methods.find(([name]) => name === 'test_call')!;
+        const rate_limit: [RateLimit] = [{ per_minute: [1], per_hour: [], per_day: [] }];
+        target.methods = [['test_call*', { ...method, name: 'test_call*', rate_limit }]];
+      },
+    });
+
+    expect(hasOwnProperty(await delegateWallet.proxy_call(callArgs('test_call')), 'Executed')).toBe(true);
+    const denied = await delegateWallet.proxy_call(callArgs('test_call_key'));
+    expect(hasOwnProperty(denied, 'Unauthorized') && hasOwnProperty(denied.Unauthorized.reason, 'RateLimited')).toBe(
+      true,
+    );
+  });
+
+  test('the wallet limit covers every delegate', async () => {
+    const owner = await ownerActor;
+    const wallets = [(await addDelegate()).delegateWallet, (await addDelegate()).delegateWallet];
+
+    const counters = await owner.get_rate_counters();
+    const used = counters.find(c => hasOwnProperty(c.key, 'Wallet'))?.last_minute ?? 0;
+    await owner.set_rate_limit([{ per_minute: [used + 1], per_hour: [], per_day: [] }]);
+    try {
+      expect(hasOwnProperty(await wallets[0].proxy_call(callArgs('test_query')), 'Executed')).toBe(true);
+      const denied = await wallets[1].proxy_call(callArgs('test_query'));
+      expect(hasOwnProperty(denied, 'Unauthorized')).toBe(true);
+      // the owner is never limited
+      expect(hasOwnProperty(await owner.proxy_call(callArgs('test_query')), 'Executed')).toBe(true);
+    } finally {
+      await owner.set_rate_limit([]);
+    }
+  });
+
+  test('queued batches count against the limits', async () => {
+    const { delegateWallet } = await addDelegate({
+      rateLimit: { per_minute: [2], per_hour: [], per_day: [] },
+      keys: ['test_call_key'],
+    });
+
+    const batch = [
+      { call: callArgs('test_call_key'), args_from: [] as [] },
+      { call: callArgs('test_call_key'), args_from: [] as [] },
+    ];
+    const result = await delegateWallet.proxy_call_batch(batch, { AllOrNothing: null });
+    if (!hasOwnProperty(result, 'Ok')) {
+      throw new Error(result.Err);
+    }
+    expect(hasOwnProperty(result.Ok.results[0], 'Queued')).toBe(true);
+
+    const denied = await delegateWallet.proxy_call(callArgs('test_query'));
+    expect(hasOwnProperty(denied, 'Unauthorized') && hasOwnProperty(denied.Unauthorized.reason, 'RateLimited')).toBe(
+      true,
+    );
+  });
+
+  test('only the owner reads the counters', async () => {
+    const stranger = await getActor<walletService>(Ed25519KeyIdentity.generate(), walletIDL, walletCanisterId);
+    await expect(stranger.get_rate_counters()).rejects.toBeTruthy();
+  });
+});
@@ -0,0 +1,83 @@
+import { _SERVICE as walletService } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId, hasOwnProperty } from '@ego-js/utils';
+
+import { Principal } from '@dfinity/principal';
+import { addDelegate, callArgs, sleep } from './proxyActor';
+
+const SECOND = BigInt(1000 * 1000 * 1000);
+
+describe('schedule', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const targetCanisterId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  const nowNanos = () => BigInt(Date.now()) * BigInt(1000 * 1000);
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(targetCanisterId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('a scheduled call runs once at its time', async () => {
+    const owner = await ownerActor;
+    const scheduled = await owner.schedule_call(callArgs('test_call'), nowNanos() + BigInt(2) * SECOND, []);
+    if (!hasOwnProperty(scheduled, 'Ok')) {
+      throw new Error(scheduled.Err);
+    }
+
+    await sleep(8000);
+    // a call that won't run again is dropped, its run is in the audit log
+    expect((await owner.list_scheduled_calls()).find(c => c.id === scheduled.Ok.id)).toBeUndefined();
+    const audit = await owner.list_audit_log(
+      {
+        caller: [identity().getPrincipal()],
+        canister: [Principal.fromText(targetCanisterId)],
+        method_name: ['test_call'],
+        from: [scheduled.Ok.next_run[0]!],
+        to: [],
+      },
+      [],
+      100,
+    );
+    expect(audit.entries.length).toBe(1);
+  });
+
+  test('a recurring call needs a sensible interval', async () => {
+    const result = await (await ownerActor).schedule_call(callArgs('test_call'), nowNanos(), [SECOND]);
+    expect(hasOwnProperty(result, 'Err')).toBe(true);
+  });
+
+  test('delegates can only schedule what they may call', async () => {
+    const { delegateWallet } = await addDelegate();
+
+    const denied = await delegateWallet.schedule_call(callArgs('not_a_method'), nowNanos() + SECOND, []);
+    expect(hasOwnProperty(denied, 'Err')).toBe(true);
+
+    const allowed = await delegateWallet.schedule_call(callArgs('test_query'), nowNanos() + BigInt(3600) * SECOND, []);
+    if (!hasOwnProperty(allowed, 'Ok')) {
+      throw new Error(allowed.Err);
+    }
+    expect((await delegateWallet.list_scheduled_calls()).map(c => c.id)).toEqual([allowed.Ok.id]);
+
+    const cancelled = await delegateWallet.cancel_scheduled_call(allowed.Ok.id);
+    expect(hasOwnProperty(cancelled, 'Ok') && cancelled.Ok.length === 1).toBe(true);
+  });
+
+  test('a delegate has a limited number of scheduled calls, dropped with its session', async () => {
+    const owner = await ownerActor;
+    const { delegate, delegateWallet } = await addDelegate();
+
+    const later = nowNanos() + BigInt(3600) * SECOND;
+    for (let i = 0; i < 16; i++) {
+      expect(hasOwnProperty(await delegateWallet.schedule_call(callArgs('test_query'), later, []), 'Ok')).toBe(true);
+    }
+    expect(hasOwnProperty(await delegateWallet.schedule_call(callArgs('test_query'), later, []), 'Err')).toBe(true);
+
+    await owner.revoke_expiry_user(delegate.getPrincipal());
+    const left = (await owner.list_scheduled_calls()).filter(c => c.user.toText() === delegate.getPrincipal().toText());
+    expect(left).toEqual([]);
+  });
+});
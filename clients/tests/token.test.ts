@@ -0,0 +1,262 @@
+import { _SERVICE as walletService, TokenPolicy } from '@/idls/wallet_canister';
+import { idlFactory as walletIDL } from '@/idls/wallet_canister.idl';
+
+import { getActor, identity, getCanisterId } from '@ego-js/utils';
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { Principal } from '@dfinity/principal';
+import { IDL } from '@dfinity/candid';
+import { method, newDelegate } from './proxyActor';
+
+// test_canister stands in for a ledger: only decisions are checked, nothing is sent
+describe('token policies', () => {
+  const walletCanisterId = getCanisterId('wallet_canister')!;
+  const ledgerId = getCanisterId('test_canister')!;
+  const ownerActor = getActor<walletService>(identity(), walletIDL, walletCanisterId);
+
+  const Account = IDL.Record({ owner: IDL.Principal, subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)) });
+  const TransferArg = IDL.Record({
+    from_subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    to: Account,
+    amount: IDL.Nat,
+    fee: IDL.Opt(IDL.Nat),
+    memo: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    created_at_time: IDL.Opt(IDL.Nat64),
+  });
+
+  const Tokens = IDL.Record({ e8s: IDL.Nat64 });
+  const LegacyTransferArgs = IDL.Record({
+    memo: IDL.Nat64,
+    amount: Tokens,
+    fee: Tokens,
+    from_subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    to: IDL.Vec(IDL.Nat8),
+    created_at_time: IDL.Opt(IDL.Record({ timestamp_nanos: IDL.Nat64 })),
+  });
+
+  const alice = Ed25519KeyIdentity.generate().getPrincipal();
+  const bob = Ed25519KeyIdentity.generate().getPrincipal();
+
+  function transfer(to: Principal, amount: bigint, fee: [] | [bigint] = []) {
+    const args = IDL.encode(
+      [TransferArg],
+      [{ from_subaccount: [], to: { owner: to, subaccount: [] }, amount, fee, memo: [], created_at_time: [] }],
+    );
+    return {
+      canister: Principal.fromText(ledgerId),
+      method_name: 'icrc1_transfer',
+      args: Array.from(new Uint8Array(args)),
+      cycles: BigInt(0),
+    };
+  }
+
+  const ApproveArgs = IDL.Record({
+    from_subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    spender: Account,
+    amount: IDL.Nat,
+    expected_allowance: IDL.Opt(IDL.Nat),
+    expires_at: IDL.Opt(IDL.Nat64),
+    fee: IDL.Opt(IDL.Nat),
+    memo: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    created_at_time: IDL.Opt(IDL.Nat64),
+  });
+
+  function approve(spender: Principal, amount: bigint, expires_at: [] | [bigint]) {
+    const args = IDL.encode(
+      [ApproveArgs],
+      [
+        {
+          from_subaccount: [],
+          spender: { owner: spender, subaccount: [] },
+          amount,
+          expected_allowance: [],
+          expires_at,
+          fee: [],
+          memo: [],
+          created_at_time: [],
+        },
+      ],
+    );
+    return { ...transfer(alice, BigInt(0)), method_name: 'icrc2_approve', args: Array.from(new Uint8Array(args)) };
+  }
+
+  function legacyTransfer(to: number[], e8s: bigint) {
+    const args = IDL.encode(
+      [LegacyTransferArgs],
+      [{ memo: BigInt(0), amount: { e8s }, fee: { e8s: BigInt(10000) }, from_subaccount: [], to, created_at_time: [] }],
+    );
+    return { ...transfer(alice, BigInt(0)), method_name: 'transfer', args: Array.from(new Uint8Array(args)) };
+  }
+
+  async function accountIdentifier(subaccount: [] | [number[]]) {
+    const result = await (await ownerActor).get_account_identifier(subaccount);
+    if (!('Ok' in result)) {
+      throw new Error(result.Err);
+    }
+    return result.Ok;
+  }
+
+  function policy(fields: Partial<TokenPolicy>): TokenPolicy {
+    return {
+      max_per_transfer: [],
+      max_per_day: [],
+      recipients: [],
+      fee: BigInt(10),
+      max_approval: [],
+      spenders: [],
+      ...fields,
+    };
+  }
+
+  async function addDelegate(token_policy: TokenPolicy | null, expiration: [] | [bigint] = []) {
+    const { delegate, delegateWallet } = await newDelegate();
+    await (
+      await ownerActor
+    ).add_expiry_user(delegate.getPrincipal(), {
+      expiration,
+      cycles: [],
+      rate_limit: [],
+      targets: [
+        {
+          canister: Principal.fromText(ledgerId),
+          methods: [method('icrc1_transfer'), method('transfer'), method('icrc2_approve')],
+          rate_limit: [],
+          deny: [],
+          all_queries: [],
+          token_policy: token_policy ? [token_policy] : [],
+          nft_policy: [],
+        },
+      ],
+    });
+    return delegateWallet;
+  }
+
+  beforeAll(async () => {
+    const owner = await ownerActor;
+    await owner.remove_proxy_black_list(Principal.fromText(ledgerId));
+    await owner.set_method_validate_type({ KEY: null });
+  });
+
+  test('transfers are capped with their fee', async () => {
+    const delegateWallet = await addDelegate(
+      policy({
+        max_per_transfer: [BigInt(1000)],
+        fee: BigInt(10),
+      }),
+    );
+    expect(await delegateWallet.check_call(transfer(alice, BigInt(990)))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(transfer(alice, BigInt(991)))).toEqual({
+      Deny: { AllowanceExceeded: { requested: BigInt(1001), remaining: BigInt(1000) } },
+    });
+    expect(await delegateWallet.check_call(transfer(alice, BigInt(900), [BigInt(200)]))).toEqual({
+      Deny: { AllowanceExceeded: { requested: BigInt(1100), remaining: BigInt(1000) } },
+    });
+  });
+
+  test('transfers only go to allowed recipients', async () => {
+    const delegateWallet = await addDelegate(
+      policy({
+        recipients: [[{ owner: alice, subaccount: [] }]],
+        fee: BigInt(10),
+      }),
+    );
+    expect(await delegateWallet.check_call(transfer(alice, BigInt(1)))).toEqual({ Execute: null });
+    const denied = await delegateWallet.check_call(transfer(bob, BigInt(1)));
+    expect('Deny' in denied && 'TransferRejected' in denied.Deny).toBe(true);
+  });
+
+  test('arguments that are not a transfer are rejected', async () => {
+    const delegateWallet = await addDelegate(policy({ fee: BigInt(0) }));
+    const denied = await delegateWallet.check_call({ ...transfer(alice, BigInt(1)), args: Array.from(new Uint8Array(IDL.encode([], []))) });
+    expect('Deny' in denied && 'TransferRejected' in denied.Deny).toBe(true);
+  });
+
+  test('the wallet computes its account identifiers', async () => {
+    const defaultId = await accountIdentifier([]);
+    expect(defaultId).toMatch(/^[0-9a-f]{64}$/);
+    expect(await accountIdentifier([Array(32).fill(0)])).toEqual(defaultId);
+    expect(await accountIdentifier([[...Array(31).fill(0), 1]])).not.toEqual(defaultId);
+    expect('Err' in (await (await ownerActor).get_account_identifier([[1]]))).toBe(true);
+  });
+
+  test('legacy ledger transfers share the limits', async () => {
+    const walletAccount = { owner: Principal.fromText(walletCanisterId), subaccount: [] as [] };
+    const delegateWallet = await addDelegate(
+      policy({
+        max_per_transfer: [BigInt(100000)],
+        recipients: [[walletAccount]],
+        fee: BigInt(10000),
+      }),
+    );
+    const allowed = Array.from(Buffer.from(await accountIdentifier([]), 'hex'));
+    const other = Array.from(Buffer.from(await accountIdentifier([[...Array(31).fill(0), 1]]), 'hex'));
+
+    expect(await delegateWallet.check_call(legacyTransfer(allowed, BigInt(90000)))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(legacyTransfer(allowed, BigInt(90001)))).toEqual({
+      Deny: { AllowanceExceeded: { requested: BigInt(100001), remaining: BigInt(100000) } },
+    });
+    const denied = await delegateWallet.check_call(legacyTransfer(other, BigInt(1)));
+    expect('Deny' in denied && 'TransferRejected' in denied.Deny).toBe(true);
+  });
+
+  test('delegates read their remaining allowance', async () => {
+    const delegateWallet = await addDelegate(
+      policy({
+        max_per_transfer: [BigInt(1000)],
+        max_per_day: [BigInt(5000)],
+        fee: BigInt(10),
+      }),
+    );
+    expect(await delegateWallet.get_token_allowance(Principal.fromText(ledgerId))).toEqual([
+      {
+        ledger: Principal.fromText(ledgerId),
+        max_per_transfer: [BigInt(1000)],
+        spent_today: BigInt(0),
+        remaining_today: [BigInt(5000)],
+      },
+    ]);
+    expect(await delegateWallet.get_token_allowance(Principal.fromText(walletCanisterId))).toEqual([]);
+  });
+
+  test('approvals are capped, expire with the session and go to allowed spenders', async () => {
+    // one hour, in nanoseconds
+    const hour = BigInt(60 * 60) * BigInt(1000000000);
+    const delegateWallet = await addDelegate(
+      policy({ max_approval: [BigInt(1000)], spenders: [[{ owner: alice, subaccount: [] }]] }),
+      [hour],
+    );
+    const soon = BigInt(Date.now()) * BigInt(1000000) + hour / BigInt(2);
+    expect(await delegateWallet.check_call(approve(alice, BigInt(1000), [soon]))).toEqual({ Execute: null });
+    expect(await delegateWallet.check_call(approve(alice, BigInt(1001), [soon]))).toEqual({
+      Deny: { AllowanceExceeded: { requested: BigInt(1001), remaining: BigInt(1000) } },
+    });
+    const rejected = [
+      approve(alice, BigInt(1), []),
+      approve(alice, BigInt(1), [soon + hour]),
+      approve(bob, BigInt(1), [soon]),
+    ];
+    for (const args of rejected) {
+      const denied = await delegateWallet.check_call(args);
+      expect('Deny' in denied && 'TransferRejected' in denied.Deny).toBe(true);
+    }
+  });
+
+  test('approvals expire with the session without a policy', async () => {
+    // one hour, in nanoseconds
+    const hour = BigInt(60 * 60) * BigInt(1000000000);
+    const delegateWallet = await addDelegate(null, [hour]);
+    const soon = BigInt(Date.now()) * BigInt(1000000) + hour / BigInt(2);
+    expect(await delegateWallet.check_call(approve(bob, BigInt(1000000), [soon]))).toEqual({ Execute: null });
+    for (const expires_at of [[], [soon + hour]] as ([] | [bigint])[]) {
+      const denied = await delegateWallet.check_call(approve(bob, BigInt(1), expires_at));
+      expect('Deny' in denied && 'TransferRejected' in denied.Deny).toBe(true);
+    }
+  });
+
+  test('only the owner manages approvals', async () => {
+    const delegateWallet = await addDelegate(policy({}));
+    expect(Array.isArray(await (await ownerActor).list_approvals())).toBe(true);
+    await expect(delegateWallet.list_approvals()).rejects.toBeTruthy();
+    await expect(delegateWallet.revoke_approvals()).rejects.toBeTruthy();
+  });
+});